@@ -0,0 +1,130 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::diagnostic::Diagnostic;
+use crate::lexer::SourceInfo;
+
+// Name des Hauptprogramms im Graph, kann kein Bezeichner sein
+const PROGRAM: &str = "";
+
+struct Function {
+    name: String,
+    // Funktionen mit Stack-Frame dürfen in einem Zyklus vorkommen
+    stack: bool,
+    info: SourceInfo,
+}
+
+struct Call {
+    from: String,
+    to: String,
+    // Erster Aufruf im Quelltext, weitere Aufrufe derselben Funktion ändern den Graph nicht
+    info: SourceInfo,
+}
+
+pub struct CallGraph {
+    functions: Vec<Function>,
+    calls: Vec<Call>,
+}
+
+impl CallGraph {
+    pub fn new() -> CallGraph {
+        return CallGraph { functions: Vec::new(), calls: Vec::new() };
+    }
+
+    pub fn add_function(&mut self, name: &str, stack: bool, info: SourceInfo) {
+        self.functions.push(Function { name: name.to_string(), stack: stack, info: info });
+    }
+
+    // from ist leer für Aufrufe aus dem Hauptprogramm
+    pub fn add_call(&mut self, from: &str, to: &str, info: SourceInfo) {
+        if self.calls.iter().any(|c| c.from == from && c.to == to) {
+            return;
+        }
+        self.calls.push(Call { from: from.to_string(), to: to.to_string(), info: info });
+    }
+
+    // Kürzester Weg von name zurück zu name (Breitensuche), als Liste der Aufrufe
+    fn find_cycle(&self, name: &str) -> Option<Vec<&Call>> {
+        let mut queue: VecDeque<&str> = VecDeque::from([name]);
+        let mut reached_by: HashMap<&str, &Call> = HashMap::new();
+
+        while let Some(f) = queue.pop_front() {
+            for c in self.calls.iter().filter(|c| c.from == f) {
+                if c.to == name {
+                    let mut chain = vec![c];
+                    let mut curr = f;
+                    while curr != name {
+                        let prev = reached_by[curr];
+                        chain.push(prev);
+                        curr = &prev.from;
+                    }
+                    chain.reverse();
+                    return Some(chain);
+                }
+                if !reached_by.contains_key(c.to.as_str()) {
+                    reached_by.insert(&c.to, c);
+                    queue.push_back(&c.to);
+                }
+            }
+        }
+        return None;
+    }
+
+    // Zyklen, in denen mindestens eine Funktion ohne Stack-Frame vorkommt.
+    // Jede solche Funktion wird höchstens in einem Zyklus gemeldet.
+    fn cycles(&self) -> Vec<Vec<&Call>> {
+        let mut reported: HashSet<&str> = HashSet::new();
+        let mut cycles = Vec::new();
+        for f in self.functions.iter().filter(|f| !f.stack) {
+            if reported.contains(f.name.as_str()) {
+                continue;
+            }
+            if let Some(chain) = self.find_cycle(&f.name) {
+                reported.extend(chain.iter().map(|c| c.from.as_str()));
+                cycles.push(chain);
+            }
+        }
+        return cycles;
+    }
+
+    pub fn check(&self) -> Vec<Diagnostic> {
+        let mut errors = Vec::new();
+        for chain in self.cycles() {
+            let first = &chain[0].from;
+            let last = chain[chain.len() - 1];
+            let decl = self.functions.iter().find(|f| &f.name == first).map(|f| f.info.clone()).unwrap_or(last.info.clone());
+
+            let mut names: Vec<&str> = chain.iter().map(|c| c.from.as_str()).collect();
+            names.push(first);
+
+            let mut d = Diagnostic::error(format!("Recursive call of function \"{}\"", first), last.info.clone())
+                .with_label(decl, "function declared here".to_string());
+            for c in &chain[..chain.len() - 1] {
+                d = d.with_label(c.info.clone(), format!("\"{}\" calls \"{}\" here", c.from, c.to));
+            }
+            errors.push(d
+                .with_note(format!("call chain: {}", names.join(" -> ")))
+                .with_note("declare the functions with \"recursive func\" or compile with --stack-frames".to_string()));
+        }
+        return errors;
+    }
+
+    // Graph im DOT-Format (Graphviz), gemeldete Zyklen sind rot
+    pub fn to_dot(&self) -> String {
+        let cyclic: Vec<(&str, &str)> = self.cycles().iter().flatten().map(|c| (c.from.as_str(), c.to.as_str())).collect();
+
+        let mut out = String::from("digraph calls {\n");
+        out.push_str("    \"<program>\" [shape=box];\n");
+        for f in &self.functions {
+            // Doppelter Rand: Funktion mit Stack-Frame
+            let shape = if f.stack { " [peripheries=2]" } else { "" };
+            out.push_str(&format!("    \"{}\"{};\n", f.name, shape));
+        }
+        for c in &self.calls {
+            let from = if c.from == PROGRAM { "<program>" } else { &c.from };
+            let color = if cyclic.contains(&(c.from.as_str(), c.to.as_str())) { " [color=red]" } else { "" };
+            out.push_str(&format!("    \"{}\" -> \"{}\"{};\n", from, c.to, color));
+        }
+        out.push_str("}\n");
+        return out;
+    }
+}
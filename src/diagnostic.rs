@@ -0,0 +1,114 @@
+use std::fmt::Debug;
+
+use crate::lexer::SourceInfo;
+
+const RED: &str = "\x1b[1;31m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Severity {
+    Error,
+}
+
+impl Severity {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub span: SourceInfo,
+    pub message: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: SourceInfo,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: String, span: SourceInfo) -> Diagnostic {
+        return Diagnostic { severity: Severity::Error, message: message, span: span, labels: Vec::new(), notes: Vec::new() };
+    }
+
+    pub fn with_note(mut self, note: String) -> Diagnostic {
+        self.notes.push(note);
+        return self;
+    }
+
+    pub fn with_label(mut self, span: SourceInfo, message: String) -> Diagnostic {
+        self.labels.push(Label { span: span, message: message });
+        return self;
+    }
+
+    // Ausgabe wie bei rustc: Quellzeile mit Markierung unter der Stelle
+    pub fn render(&self, source: &str, color: bool) -> String {
+        let paint = |c: &str, t: &str| if color { format!("{}{}{}", c, t, RESET) } else { t.to_string() };
+
+        let mut width = self.span.line.to_string().len();
+        for l in &self.labels {
+            width = usize::max(width, l.span.line.to_string().len());
+        }
+        let pad = " ".repeat(width);
+
+        let mut out = format!("{}{}", paint(RED, self.severity.name()), paint(BOLD, &format!(": {}", self.message)));
+        out.push_str(&format!("\n{}{} {}:{}:{}", pad, paint(BLUE, "-->"), self.span.file, self.span.line, self.span.column));
+        out.push_str(&format!("\n{} {}", pad, paint(BLUE, "|")));
+        out.push_str(&render_snippet(source, &self.span, '^', "", width, RED, &paint));
+
+        for l in &self.labels {
+            if l.span.file != self.span.file {
+                out.push_str(&format!("\n{}{} {}:{}:{}", pad, paint(BLUE, ":::"), l.span.file, l.span.line, l.span.column));
+            }
+            out.push_str(&format!("\n{} {}", pad, paint(BLUE, "|")));
+            out.push_str(&render_snippet(source, &l.span, '-', &l.message, width, BLUE, &paint));
+        }
+
+        if !self.notes.is_empty() {
+            out.push_str(&format!("\n{} {}", pad, paint(BLUE, "|")));
+        }
+        for n in &self.notes {
+            out.push_str(&format!("\n{} {} {}", pad, paint(BLUE, "="), paint(BOLD, &format!("note: {}", n))));
+        }
+        return out;
+    }
+}
+
+// Tabs werden als vier Leerzeichen dargestellt, damit die Markierung passt
+fn display_width(text: &str) -> usize {
+    return text.chars().map(|c| if c == '\t' { 4 } else { 1 }).sum();
+}
+
+fn render_snippet(source: &str, span: &SourceInfo, mark: char, label: &str, width: usize, c: &str, paint: &dyn Fn(&str, &str) -> String) -> String {
+    if span.offset > source.len() || !source.is_char_boundary(span.offset) {
+        return String::new();
+    }
+    let line_begin = source[..span.offset].rfind('\n').map_or(0, |p| p + 1);
+    let line_end = source[span.offset..].find('\n').map_or(source.len(), |p| span.offset + p);
+    let text = source[line_begin..line_end].trim_end_matches('\r');
+
+    let chars: Vec<char> = text.chars().collect();
+    let start = usize::min(span.column.saturating_sub(1), chars.len());
+    let end = usize::min(span.end_column.saturating_sub(1), chars.len());
+    let prefix: String = chars[..start].iter().collect();
+    let marked: String = chars[start..usize::max(start, end)].iter().collect();
+
+    let len = usize::max(display_width(&marked), 1);
+    let mut underline = format!("{}{}", " ".repeat(display_width(&prefix)), mark.to_string().repeat(len));
+    if !label.is_empty() {
+        underline.push_str(&format!(" {}", label));
+    }
+
+    let gutter = format!("{:>w$} |", span.line, w = width);
+    let empty = format!("{:>w$} |", "", w = width);
+    return format!("\n{} {}\n{} {}", paint(BLUE, &gutter), text.replace('\t', "    "), paint(BLUE, &empty), paint(c, &underline));
+}
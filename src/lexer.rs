@@ -1,6 +1,14 @@
-use std::vec;
 use std::fmt::Debug;
 
+use unicode_xid::UnicodeXID;
+
+use crate::diagnostic::Diagnostic;
+
+// Die CPU kann nur Zahlen von -999999 bis 999999 speichern
+pub const WORD_MAX: isize = 999999;
+
+const KEYWORDS: &[&str] = &["var", "arr", "const", "func", "recursive", "if", "else", "while", "for", "to", "break", "continue", "return", "call", "deref", "addr"];
+
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum TokenType {
     Identifier,
@@ -11,6 +19,7 @@ pub enum TokenType {
     Keyword,
     Newline,
     Comma,
+    String,
     EOF,
 }
 
@@ -18,14 +27,20 @@ pub enum TokenType {
 pub struct SourceInfo {
     pub line: usize,
     pub column: usize,
+    pub end_column: usize,
+    // Byte-Offset des ersten Zeichens im Quelltext
+    pub offset: usize,
     pub file: String,
 }
 
 impl SourceInfo {
-    pub fn print(&self) -> String { return format!("(\"{}\": {}, {})", self.file, self.line, self.column) }
+    pub fn new(l: usize, c: usize, o: usize, f: String) -> SourceInfo {
+        return SourceInfo { line: l, column: c, end_column: c, offset: o, file: f };
+    }
 
-    pub fn new(l: usize, c: usize, f: String) -> SourceInfo {
-        return SourceInfo { line: l, column: c, file: f };
+    pub fn with_len(mut self, len: usize) -> SourceInfo {
+        self.end_column = self.column + len;
+        return self;
     }
 }
 
@@ -45,114 +60,311 @@ impl Token {
     }
 
     pub fn equals(&self, t: TokenType, v: &str) -> bool {
-        return self.ttype == t && self.value == v.to_string();
+        return self.ttype == t && self.value == v;
     }
 
-    pub fn position(&self) -> String { return self.info.print() }
+    pub fn describe(&self) -> String {
+        return match self.ttype {
+            TokenType::Newline => "end of line".to_string(),
+            TokenType::EOF => "end of file".to_string(),
+            TokenType::Equals => "\'=\'".to_string(),
+            TokenType::Comma => "\',\'".to_string(),
+            TokenType::String => format!("\"{}\"", self.value.escape_debug()),
+            _ => format!("\'{}\'", self.value),
+        };
+    }
+}
+
+// Zeichen nach einem '\\' in Zeichen- und Stringliteralen
+fn unescape(c: char) -> Option<char> {
+    return match c {
+        'n' => Some('\n'),
+        't' => Some('\t'),
+        'r' => Some('\r'),
+        '0' => Some('\0'),
+        '\\' => Some('\\'),
+        '\'' => Some('\''),
+        '"' => Some('"'),
+        _ => None,
+    };
 }
 
-pub fn lexer(input_string: String) -> Vec<Token> {
-    let mut input: Vec<char> = input_string.chars().collect();
-    let filename: String = "file".to_string();
+pub fn lexer(input_string: String, filename: String) -> Result<Vec<Token>, Vec<Diagnostic>> {
+    let mut input: Vec<char> = Vec::new();
+    let mut offsets: Vec<usize> = Vec::new();
+    for (o, c) in input_string.char_indices() {
+        offsets.push(o);
+        input.push(c);
+    }
     input.push(' ');
+    offsets.push(input_string.len());
     let mut tokenlist: Vec<Token> = Vec::new();
+    let mut errors: Vec<Diagnostic> = Vec::new();
 
     let mut linectr: usize = 1;
-    let mut colctr: usize = 1;
+    // Index des ersten Zeichens der aktuellen Zeile, daraus ergibt sich die Spalte
+    let mut line_start: usize = 0;
 
     let mut i: usize = 0;
     while i < input.len()
     {
+        let start = i;
+        let info = SourceInfo::new(linectr, i - line_start + 1, offsets[i], filename.clone());
+
+        // Line comment (das '\n' am Ende bleibt stehen, damit die Zeile gezählt wird)
+        if input[i] == '/' && input[i+1] == '/' {
+            while i < input.len() - 1 && input[i] != '\n' {
+                i += 1;
+            }
+            continue;
+        }
+
+        // Block comment, darf verschachtelt werden
+        if input[i] == '/' && input[i+1] == '*' {
+            let mut depth: usize = 0;
+            while i < input.len() - 1 {
+                if input[i] == '/' && input[i+1] == '*' {
+                    depth += 1;
+                    i += 2;
+                } else if input[i] == '*' && input[i+1] == '/' {
+                    depth -= 1;
+                    i += 2;
+                    if depth == 0 {
+                        break;
+                    }
+                } else {
+                    if input[i] == '\n' {
+                        linectr += 1;
+                        line_start = i + 1;
+                    }
+                    i += 1;
+                }
+            }
+            if depth != 0 {
+                errors.push(Diagnostic::error("Unterminated block comment".to_string(), info.with_len(2)));
+            }
+            continue;
+        }
+
         // Identifier / Keyword
-        if input[i].is_ascii_alphabetic() {
+        if input[i] == '_' || UnicodeXID::is_xid_start(input[i]) {
             let mut tmp = String::new();
             tmp.push(input[i]);
-            while input[i+1].is_ascii_alphabetic() {
+            while UnicodeXID::is_xid_continue(input[i+1]) {
                 i += 1;
                 tmp.push(input[i]);
             }
-            if vec!["var", "arr", "func", "if", "while", "return", "call", "deref", "addr"].contains(&tmp.as_str()) {
-                tokenlist.push(Token::new(TokenType::Keyword, tmp, SourceInfo::new(linectr, colctr, filename.clone())));
+            i += 1;
+            if KEYWORDS.contains(&tmp.as_str()) {
+                tokenlist.push(Token::new(TokenType::Keyword, tmp, info.with_len(i - start)));
             } else {
-                tokenlist.push(Token::new(TokenType::Identifier, tmp, SourceInfo::new(linectr, colctr, filename.clone())));
+                tokenlist.push(Token::new(TokenType::Identifier, tmp, info.with_len(i - start)));
             }
-
-            i += 1;
             continue;
         }
 
-        // Number
-        if input[i].is_digit(10) {
-            let mut tmp: isize = input[i].to_digit(10).unwrap() as isize;
-            while input[i+1].is_digit(10) {
+        // Number (dezimal, "0x" hexadezimal, "0b" binär, '_' trennt Ziffern)
+        if input[i].is_ascii_digit() {
+            let mut radix: u32 = 10;
+            if input[i] == '0' && "xXbB".contains(input[i+1]) {
+                radix = if "xX".contains(input[i+1]) { 16 } else { 2 };
+                i += 2;
+            }
+
+            let mut tmp: isize = 0;
+            let mut digits: usize = 0;
+            while input[i].is_digit(radix) || (input[i] == '_' && digits > 0) {
+                if let Some(d) = input[i].to_digit(radix) {
+                    tmp = tmp.saturating_mul(radix as isize).saturating_add(d as isize);
+                    digits += 1;
+                }
                 i += 1;
-                tmp *= 10;
-                tmp += input[i].to_digit(10).unwrap() as isize;
             }
-            tokenlist.push(Token::new(TokenType::Number, format!("{}", tmp), SourceInfo::new(linectr, colctr, filename.clone())));
 
+            if UnicodeXID::is_xid_continue(input[i]) {
+                errors.push(Diagnostic::error(format!("Invalid digit \'{}\' in number literal", input[i]), info.clone().with_len(i - start + 1)));
+                while UnicodeXID::is_xid_continue(input[i]) {
+                    i += 1;
+                }
+                continue;
+            }
+            if digits == 0 {
+                errors.push(Diagnostic::error("Number literal has no digits".to_string(), info.with_len(i - start)));
+                continue;
+            }
+            // '_' trennt nur Ziffern, "1_" ist vermutlich ein Tippfehler
+            if input[i - 1] == '_' {
+                errors.push(Diagnostic::error("Number literal must not end with \'_\'".to_string(), info.with_len(i - start)));
+                continue;
+            }
+            if tmp > WORD_MAX {
+                errors.push(Diagnostic::error("Number literal out of range".to_string(), info.clone().with_len(i - start))
+                    .with_note(format!("numbers must be between {} and {}", -WORD_MAX, WORD_MAX)));
+            }
+
+            tokenlist.push(Token::new(TokenType::Number, format!("{}", tmp), info.with_len(i - start)));
+            continue;
+        }
+
+        // Character literal, wird zu einer Zahl
+        if input[i] == '\'' {
+            let mut c = input.get(i + 1).copied().unwrap_or('\n');
+            let mut len: usize = 3;
+            if c == '\\' {
+                len = 4;
+                // Hinter dem Eingabeende steht nur das angehängte Leerzeichen
+                if i + 2 >= input.len() - 1 || input[i + 2] == '\n' {
+                    errors.push(Diagnostic::error("Unterminated character literal".to_string(), info.with_len(2)));
+                    i += 2;
+                    continue;
+                }
+                let e = input[i + 2];
+                c = match unescape(e) {
+                    Some(c) => c,
+                    None => {
+                        errors.push(Diagnostic::error(format!("Unknown escape sequence \'\\{}\'", e.escape_debug()), info.with_len(3)));
+                        i += if input.get(i + 3) == Some(&'\'') { 4 } else { 3 };
+                        continue;
+                    },
+                };
+            } else if c == '\n' || c == '\'' {
+                let len = if c == '\'' { 2 } else { 1 };
+                errors.push(Diagnostic::error("Empty character literal".to_string(), info.with_len(len)));
+                i += len;
+                continue;
+            }
+
+            if input.get(i + len - 1) != Some(&'\'') {
+                errors.push(Diagnostic::error("Unterminated character literal".to_string(), info.with_len(len - 1)));
+                i += len - 1;
+                continue;
+            }
+            if c as isize > WORD_MAX {
+                errors.push(Diagnostic::error("Character literal out of range".to_string(), info.clone().with_len(len))
+                    .with_note(format!("numbers must be between {} and {}", -WORD_MAX, WORD_MAX)));
+            }
+
+            tokenlist.push(Token::new(TokenType::Number, format!("{}", c as isize), info.with_len(len)));
+            i += len;
+            continue;
+        }
+
+        // String, wird zu einem Array aus Zeichencodes
+        if input[i] == '"' {
+            let mut tmp = String::new();
             i += 1;
+            loop {
+                if input[i] == '"' {
+                    i += 1;
+                    break;
+                }
+                if input[i] == '\n' || i == input.len() - 1 {
+                    errors.push(Diagnostic::error("Unterminated string literal".to_string(), info.clone().with_len(i - start)));
+                    break;
+                }
+                if input[i] == '\\' {
+                    // "\" am Zeilen- oder Dateiende: die Prüfung oben meldet den offenen String
+                    if input[i+1] == '\n' || i + 1 == input.len() - 1 {
+                        i += 1;
+                        continue;
+                    }
+                    match unescape(input[i+1]) {
+                        Some(c) => tmp.push(c),
+                        None => {
+                            let esc = SourceInfo::new(linectr, i - line_start + 1, offsets[i], filename.clone());
+                            errors.push(Diagnostic::error(format!("Unknown escape sequence \'\\{}\'", input[i+1].escape_debug()), esc.with_len(2)));
+                        },
+                    }
+                    i += 2;
+                    continue;
+                }
+                tmp.push(input[i]);
+                i += 1;
+            }
+
+            if tmp.chars().any(|c| c as isize > WORD_MAX) {
+                errors.push(Diagnostic::error("Character in string literal out of range".to_string(), info.clone().with_len(i - start))
+                    .with_note(format!("numbers must be between {} and {}", -WORD_MAX, WORD_MAX)));
+            }
+
+            tokenlist.push(Token::new(TokenType::String, tmp, info.with_len(i - start)));
+            continue;
+        }
+
+        // Multi letter operators (vor den einzelnen, sonst wird "<=" zu "<" "=")
+        if "=<>!".contains(input[i]) && input[i+1] == '=' {
+            tokenlist.push(Token::new(TokenType::Operator, format!("{}=", input[i]), info.with_len(2)));
+            i += 2;
             continue;
         }
 
         // Single letter operators
-        if "+-*<>".contains(input[i]) {
-            tokenlist.push(Token::new(TokenType::Operator, String::from(input[i]), SourceInfo::new(linectr, colctr, filename.clone())));
+        if "+-*/%<>".contains(input[i]) {
+            tokenlist.push(Token::new(TokenType::Operator, String::from(input[i]), info.with_len(1)));
 
             i += 1;
             continue;
         }
 
-        // Multi letter operators
-        if "=<>!".contains(input[i]) && input[i+1] == '=' {
-            tokenlist.push(Token::new(TokenType::Operator, format!("{}=", input[i]), SourceInfo::new(linectr, colctr, filename.clone())));
+        // Logical operators
+        if (input[i] == '&' && input[i+1] == '&') || (input[i] == '|' && input[i+1] == '|') {
+            tokenlist.push(Token::new(TokenType::Operator, format!("{}{}", input[i], input[i+1]), info.with_len(2)));
             i += 2;
             continue;
         }
+        if input[i] == '!' {
+            tokenlist.push(Token::new(TokenType::Operator, String::from(input[i]), info.with_len(1)));
+            i += 1;
+            continue;
+        }
 
         //Equals
         if input[i] == '=' {
-            tokenlist.push(Token::from_type(TokenType::Equals, SourceInfo::new(linectr, colctr, filename.clone())));
+            tokenlist.push(Token::from_type(TokenType::Equals, info.with_len(1)));
             i += 1;
             continue;
         }
 
         //Parenthesis
         if "()[]{}".contains(input[i]) {
-            tokenlist.push(Token::new(TokenType::Parenthesis, String::from(input[i]), SourceInfo::new(linectr, colctr, filename.clone())));
+            tokenlist.push(Token::new(TokenType::Parenthesis, String::from(input[i]), info.with_len(1)));
             i += 1;
             continue;
         }
 
         //Seperator
         if input[i] == ',' {
-            tokenlist.push(Token::from_type(TokenType::Comma, SourceInfo::new(linectr, colctr, filename.clone())));
+            tokenlist.push(Token::from_type(TokenType::Comma, info.with_len(1)));
             i += 1;
             continue;
         }
 
         //Newline
         if input[i] == '\n' {
-            tokenlist.push(Token::from_type(TokenType::Newline, SourceInfo::new(linectr, colctr, filename.clone())));
+            tokenlist.push(Token::from_type(TokenType::Newline, info.with_len(1)));
             linectr += 1;
-            colctr = 1;
 
             i += 1;
+            line_start = i;
+            continue;
+        }
 
-            // Comments
-            if input[i] == '/' && input[i+1] == '/' {
-                while input[i] != '\n' {
-                    i += 1;
-                }
-                i += 1;
-            }
+        // Leerzeichen, Tabs und '\r' (Windows-Zeilenenden) werden übersprungen
+        if " \t\r".contains(input[i]) {
+            i += 1;
             continue;
         }
 
+        errors.push(Diagnostic::error(format!("Invalid character \'{}\'", input[i].escape_debug()), info.with_len(1)));
         i += 1;
-        colctr += 1;
     }
 
-    tokenlist.push(Token::from_type(TokenType::EOF, SourceInfo::new(linectr, colctr, filename.clone())));
-    return tokenlist;
-}
\ No newline at end of file
+    // Das angehängte Leerzeichen zählt nicht mit
+    let column = input.len() - line_start;
+    tokenlist.push(Token::from_type(TokenType::EOF, SourceInfo::new(linectr, column, input_string.len(), filename.clone()).with_len(1)));
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    return Ok(tokenlist);
+}
@@ -1,12 +1,20 @@
+#![allow(clippy::needless_return, clippy::redundant_field_names, clippy::upper_case_acronyms, clippy::result_large_err)]
+
+mod callgraph;
+mod diagnostic;
 mod lexer;
 mod parser;
+#[cfg(test)]
+mod tests;
 
+use diagnostic::Diagnostic;
 use lexer::{lexer, Token};
 
 use clap::Parser;
 use std::fs::File;
-use std::io::{prelude::*, self};
+use std::io::prelude::*;
 use std::path::Path;
+use std::process::exit;
 
 use crate::parser::AST;
 
@@ -24,6 +32,33 @@ struct Args {
     print_ast: bool,
     #[arg(long)]
     print_code: bool,
+    #[arg(long, default_value_t=20)]
+    max_errors: usize,
+    #[arg(long)]
+    color: bool,
+    // Alle Funktionen mit Stack-Frames erzeugen, erlaubt Rekursion
+    #[arg(long)]
+    stack_frames: bool,
+    // Aufrufgraph im DOT-Format in diese Datei schreiben
+    #[arg(long)]
+    call_graph: Option<String>,
+}
+
+fn fail(msg: String) -> ! {
+    eprintln!("{}", msg);
+    exit(1);
+}
+
+fn report<T>(res: Result<T, Vec<Diagnostic>>, source: &str, color: bool) -> T {
+    match res {
+        Ok(val) => val,
+        Err(diagnostics) => {
+            for d in &diagnostics {
+                eprintln!("{}\n", d.render(source, color));
+            }
+            fail(format!("Kompilierung fehlgeschlagen: {} Fehler", diagnostics.len()));
+        },
+    }
 }
 
 fn main() {
@@ -31,40 +66,46 @@ fn main() {
     let infile_path = Path::new(&args.infile);
     let outfile_path = Path::new(&args.outfile);
 
-    let mut infile = match File::open(&infile_path) {
-        Err(why) => panic!("Eingabedatei konnte nicht geöffnet werden: {}", why),
-        Ok(file) => file,
-    };
-    let mut outfile = match File::create(&outfile_path) {
-        Err(why) => panic!("Konnte Ausgabedatei nicht erstellen: {}", why),
+    let mut infile = match File::open(infile_path) {
+        Err(why) => fail(format!("Eingabedatei konnte nicht geöffnet werden: {}", why)),
         Ok(file) => file,
     };
 
     let mut infile_text = String::new();
-    match infile.read_to_string(&mut infile_text) {
-        Err(why) => panic!("Fehler beim Lesen der Eingabedatei: {}", why),
-        Ok(_) => {},
+    if let Err(why) = infile.read_to_string(&mut infile_text) {
+        fail(format!("Fehler beim Lesen der Eingabedatei: {}", why));
     }
 
     // Hier passiert der shit
 
-    let tokens: Vec<Token> = lexer(infile_text);
+    let tokens: Vec<Token> = report(lexer(infile_text.clone(), args.infile.clone()), &infile_text, args.color);
     if args.print_tokens {
         println!("{:#?}\n", tokens);
     }
-    
-    let ast: AST = parser::parse(tokens);
+
+    let ast: AST = report(parser::parse(tokens, args.max_errors), &infile_text, args.color);
     if args.print_ast {
         println!("{:#?}\n", ast);
     }
 
-    let code: String = ast.codegen();
+    if let Some(path) = &args.call_graph {
+        let dot = ast.call_graph(args.stack_frames).to_dot();
+        if let Err(why) = File::create(path).and_then(|mut f| f.write_all(dot.as_bytes())) {
+            fail(format!("Fehler beim Schreiben des Aufrufgraphen: {}", why));
+        }
+    }
+
+    let code: String = report(ast.codegen(args.stack_frames), &infile_text, args.color);
     if args.print_code {
         println!("{}", code);
     }
 
-    match outfile.write_all(code.as_bytes()) {
-        Err(why) => panic!("Fehler beim Schreiben in die Ausgabedatei: {}", why),
-        Ok(_) => {},
+    // Ausgabedatei erst erstellen, wenn die Kompilierung erfolgreich war
+    let mut outfile = match File::create(outfile_path) {
+        Err(why) => fail(format!("Konnte Ausgabedatei nicht erstellen: {}", why)),
+        Ok(file) => file,
+    };
+    if let Err(why) = outfile.write_all(code.as_bytes()) {
+        fail(format!("Fehler beim Schreiben in die Ausgabedatei: {}", why));
     }
-}
\ No newline at end of file
+}
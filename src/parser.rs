@@ -1,32 +1,48 @@
-use std::{fmt::Debug, collections::{HashSet, HashMap}};
+use std::{fmt::Debug, collections::{BTreeMap, HashSet, HashMap}};
 
-use crate::lexer::{Token, TokenType};
+use crate::callgraph::CallGraph;
+use crate::diagnostic::Diagnostic;
+use crate::lexer::{SourceInfo, Token, TokenType, WORD_MAX};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 enum BinaryOperatorType {
     Plus,
     Minus,
     Times,
+    Divide,
+    Modulo,
     LessThan,
     GreaterThan,
+    LessEquals,
+    GreaterEquals,
     Equals,
     NotEquals,
+    And,
+    Or,
 }
 
 #[derive(Debug)]
 enum UnaryOperatorType {
     Negation,
+    Not,
     Deref,
 }
 
 trait Statement: Debug {
-    fn codegen(&self, state: &mut CodeGenState);
+    fn codegen(&self, state: &mut CodeGenState) -> Result<(), Diagnostic>;
+    // Wird vor codegen für das ganze Programm aufgerufen, registriert alle Funktionen
+    fn declare(&self, _state: &mut CodeGenState) -> Result<(), Diagnostic> { return Ok(()); }
 }
 trait Expression: Debug {
-    fn codegen(&self, state: &mut CodeGenState);
-    //fn static_eval(&self) -> Option<usize>;
+    fn codegen(&self, state: &mut CodeGenState) -> Result<(), Diagnostic>;
+    // Wert zur Compile-Zeit, falls der Ausdruck konstant ist
+    fn static_eval(&self, _state: &CodeGenState) -> Option<isize> { return None; }
 }
 
+// Ergebnis von static_eval nach einem Überlauf. Es bleibt in allen weiteren Operationen
+// erhalten und liegt außerhalb von WORD_MAX, wird also als "out of range" gemeldet.
+const OVERFLOW: isize = isize::MAX;
+
 
 #[derive(Debug, Clone)]
 struct MemoryAllocation {
@@ -34,6 +50,9 @@ struct MemoryAllocation {
     start: usize,
     end: usize,
     scope: usize,
+    // Deklaration im Quelltext, fehlt bei Temporären, Parametern und statischen Zellen
+    info: Option<SourceInfo>,
+    array: bool,
 }
 
 impl MemoryAllocation {
@@ -42,10 +61,39 @@ impl MemoryAllocation {
 
 struct MemoryTracker {
     allocations: Vec<MemoryAllocation>,
+    // Erste Adresse, die noch nie belegt war (Größe eines Stack-Frames)
+    size: usize,
+    // Alle jemals belegten Bereiche in Reihenfolge
+    history: Vec<(usize, usize)>,
+    // Kleinste Adresse, die get_free vergibt
+    floor: usize,
 }
 
 impl MemoryTracker {
-    fn new() -> MemoryTracker { return MemoryTracker { allocations: Vec::new() }; }
+    fn new() -> MemoryTracker { return MemoryTracker { allocations: Vec::new(), size: 0, history: Vec::new(), floor: 0 }; }
+
+    fn push(&mut self, m: MemoryAllocation) {
+        self.size = usize::max(self.size, m.end + 1);
+        self.history.push((m.start, m.end));
+        self.allocations.push(m);
+    }
+
+    fn mark(&self) -> usize {
+        return self.history.len();
+    }
+
+    // Alles, was seit mark belegt wurde, bleibt bis zum Ende des Programms belegt.
+    // Eine Funktion benutzt ihren Speicher bei jedem Aufruf, er darf danach nicht neu vergeben werden.
+    fn keep_since(&mut self, mark: usize, name: &str) {
+        let used: Vec<(usize, usize)> = self.history[mark..].to_vec();
+        for (start, end) in used {
+            for addr in start..=end {
+                if !self.is_addr_occupied(addr) {
+                    self.push(MemoryAllocation { name: name.to_string(), start: addr, end: addr, scope: usize::MAX, info: None, array: false });
+                }
+            }
+        }
+    }
 
     fn is_addr_occupied(&self, addr: usize) -> bool {
         for m in &self.allocations {
@@ -57,7 +105,7 @@ impl MemoryTracker {
     }
 
     fn get_free(&self, len: usize) -> usize {
-        let mut addr: usize = usize::MAX;
+        let mut addr: usize = self.floor.wrapping_sub(1);
         'outer: loop {
             addr = addr.wrapping_add(1);
 
@@ -77,41 +125,38 @@ impl MemoryTracker {
         }
     }
 
-    fn alloc(&mut self, name: &String) {
+    fn alloc(&mut self, name: &str, info: Option<SourceInfo>) {
         let addr = self.get_free(1);
-        self.allocations.push(MemoryAllocation { name: name.clone(), start: addr, end: addr, scope: 0 });
+        self.push(MemoryAllocation { name: name.to_string(), start: addr, end: addr, scope: 0, info: info, array: false });
     }
 
     fn alloc_temp(&mut self) -> usize {
         let addr = self.get_free(1);
-        self.allocations.push(MemoryAllocation { name: "".to_string(), start: addr, end: addr, scope: 0 });
+        self.push(MemoryAllocation { name: "".to_string(), start: addr, end: addr, scope: 0, info: None, array: false });
         return addr;
     }
 
-    fn alloc_overlay(&mut self, name: &String, addr: usize) {
-        self.allocations.push(MemoryAllocation { name: name.clone(), start: addr, end: addr, scope: 0 });
+    // Bleibt bis zum Ende des Programms belegt (z.B. für Laufzeitroutinen)
+    fn alloc_static(&mut self, name: &str, len: usize) -> usize {
+        let addr = self.get_free(len);
+        self.push(MemoryAllocation { name: name.to_string(), start: addr, end: addr + len - 1, scope: usize::MAX, info: None, array: false });
+        return addr;
     }
 
-    fn alloc_array(&mut self, name: &String, len: usize) {
-        let addr = self.get_free(len);
-        self.allocations.push(MemoryAllocation { name: name.clone(), start: addr, end: addr + len - 1, scope: 0 });
+    fn alloc_overlay(&mut self, name: &str, addr: usize) {
+        self.push(MemoryAllocation { name: name.to_string(), start: addr, end: addr, scope: 0, info: None, array: false });
     }
 
-    fn alloc_temp_array(&mut self, len: usize) -> usize {
+    fn alloc_array(&mut self, name: &str, len: usize, info: SourceInfo) {
         let addr = self.get_free(len);
-        self.allocations.push(MemoryAllocation { name: "".to_string(), start: addr, end: addr + len - 1, scope: 0 });
-        return addr;
+        self.push(MemoryAllocation { name: name.to_string(), start: addr, end: addr + len - 1, scope: 0, info: Some(info), array: true });
     }
 
+    // Die neueste Allokation, sie verdeckt ältere mit demselben Namen
     fn dealloc_name(&mut self, name: &String) {
-        let mut i = 0;
-        for m in &self.allocations {
-            if &m.name == name {
-                break;
-            }
-            i += 1;
+        if let Some(i) = self.allocations.iter().rposition(|m| &m.name == name) {
+            self.allocations.remove(i);
         }
-        self.allocations.remove(i);
     }
 
     fn dealloc_addr(&mut self, addr: usize) {
@@ -125,42 +170,313 @@ impl MemoryTracker {
         self.allocations.remove(i);
     }
 
-    fn get(&self, name: &String) -> usize {
-        for m in &self.allocations {
-            if &m.name == name {
-                return m.start;
-            }
-        }
-
-        panic!("Variable not found: \"{}\"!", name);
+    // Die zuletzt deklarierte Variable mit dem Namen, also die im innersten Scope
+    fn find(&self, name: &String) -> Option<&MemoryAllocation> {
+        return self.allocations.iter().rev().find(|m| &m.name == name);
     }
 
     fn inc_scope(&mut self) {
         for m in &mut self.allocations {
-            m.scope += 1;
+            // Statische Allokationen haben usize::MAX und bleiben dort
+            m.scope = m.scope.saturating_add(1);
         }
     }
 
     fn dec_scope(&mut self) {
-        let mut del: Vec<String> = Vec::new();
-
+        self.allocations.retain(|m| m.scope > 0);
         for m in &mut self.allocations {
-            if m.scope == 0 {
-                del.push(m.name.clone());
-            } else {
-                m.scope -= 1;
-            }
+            m.scope -= 1;
         }
+    }
+}
+
+// Zweierpotenzen bis WORD_MAX (2^19 <= 999999 < 2^20) für die Multiplikation
+const MUL_TABLE: usize = 20;
+const _: () = assert!(1 << (MUL_TABLE - 1) <= WORD_MAX && WORD_MAX < 1 << MUL_TABLE);
+
+// Laufzeitroutinen, die nur einmal pro Programm erzeugt und per "call" aufgerufen werden
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Runtime {
+    Mul,
+    DivMod,
+}
+
+impl Runtime {
+    fn label(&self) -> &'static str {
+        match self {
+            Runtime::Mul => "rt_mul",
+            Runtime::DivMod => "rt_divmod",
+        }
+    }
+
+    // Anzahl der Speicherzellen für Parameter, Ergebnisse und Zwischenwerte
+    fn cells(&self) -> usize {
+        match self {
+            Runtime::Mul => 8 + MUL_TABLE,
+            Runtime::DivMod => 8,
+        }
+    }
+
+    fn codegen(&self, state: &mut CodeGenState, base: usize) {
+        match self {
+            Runtime::Mul => {
+                // Eingabe: a = base, b = base+1; Ausgabe: Produkt = base+2
+                // Erst werden die Zweierpotenzen bis |b| in eine Tabelle geschrieben, dann wird sie
+                // rückwärts durchlaufen: res = 2 * res, und wenn die Potenz in rem passt, res + |a|.
+                // Beide Durchläufe brauchen O(log |b|) Schritte.
+                let (a, b, res, rem) = (base, base + 1, base + 2, base + 3);
+                let (aa, p, x, ptr) = (base + 4, base + 5, base + 6, base + 7);
+                let table = base + 8;
+
+                let l_up = state.gen_label();
+                let l_down = state.gen_label();
+                let l_skip = state.gen_label();
+                let l_signs = state.gen_label();
+                let l_aneg = state.gen_label();
+                let l_neg = state.gen_label();
+                let l_ret = state.gen_label();
+
+                state.adds(format!("label {}", self.label()));
+
+                // aa = |a|, rem = |b|
+                let l_apos = state.gen_label();
+                state.adds(format!("lda {}", a));
+                state.adds(format!("jp {}", l_apos));
+                state.add("neg");
+                state.adds(format!("label {}", l_apos));
+                state.adds(format!("sta {}", aa));
+
+                let l_bpos = state.gen_label();
+                state.adds(format!("lda {}", b));
+                state.adds(format!("jp {}", l_bpos));
+                state.add("neg");
+                state.adds(format!("label {}", l_bpos));
+                state.adds(format!("sta {}", rem));
+                state.add("mka 0");
+                state.adds(format!("sta {}", res));
+
+                // Tabelle: 1, 2, 4, ... bis zur größten Potenz p <= rem
+                state.adds(format!("mka {}", table));
+                state.adds(format!("sta {}", ptr));
+                state.add("mka 1");
+                state.adds(format!("sta {}", p));
+                state.adds(format!("stad {}", ptr));
+
+                // rem - p - p < 0 statt rem < 2p, damit nichts überläuft
+                state.adds(format!("label {}", l_up));
+                state.adds(format!("lda {}", p));
+                state.add("neg");
+                state.adds(format!("sta {}", x));
+                state.adds(format!("add {}", rem));
+                state.adds(format!("add {}", x));
+                state.adds(format!("jn {}", l_down));
+                state.adds(format!("lda {}", p));
+                state.adds(format!("add {}", p));
+                state.adds(format!("sta {}", p));
+                state.add("mka 1");
+                state.adds(format!("add {}", ptr));
+                state.adds(format!("sta {}", ptr));
+                state.adds(format!("lda {}", p));
+                state.adds(format!("stad {}", ptr));
+                state.adds(format!("jmp {}", l_up));
+
+                // Von der größten Potenz abwärts
+                state.adds(format!("label {}", l_down));
+                state.adds(format!("lda {}", res));
+                state.adds(format!("add {}", res));
+                state.adds(format!("sta {}", res));
+                state.adds(format!("ldad {}", ptr));
+                state.add("neg");
+                state.adds(format!("add {}", rem));
+                state.adds(format!("jn {}", l_skip));
+                state.adds(format!("sta {}", rem));
+                state.adds(format!("lda {}", res));
+                state.adds(format!("add {}", aa));
+                state.adds(format!("sta {}", res));
+
+                state.adds(format!("label {}", l_skip));
+                state.adds(format!("mka -{}", table));
+                state.adds(format!("add {}", ptr));
+                state.adds(format!("jz {}", l_signs));
+                state.add("mka -1");
+                state.adds(format!("add {}", ptr));
+                state.adds(format!("sta {}", ptr));
+                state.adds(format!("jmp {}", l_down));
+
+                // Negativ, wenn genau ein Faktor negativ ist
+                state.adds(format!("label {}", l_signs));
+                state.adds(format!("lda {}", a));
+                state.adds(format!("jn {}", l_aneg));
+                state.adds(format!("lda {}", b));
+                state.adds(format!("jn {}", l_neg));
+                state.add("ret");
+
+                state.adds(format!("label {}", l_aneg));
+                state.adds(format!("lda {}", b));
+                state.adds(format!("jn {}", l_ret));
+
+                state.adds(format!("label {}", l_neg));
+                state.adds(format!("lda {}", res));
+                state.add("neg");
+                state.adds(format!("sta {}", res));
+                state.adds(format!("label {}", l_ret));
+                state.add("ret");
+            },
+            Runtime::DivMod => {
+                // Eingabe: a = base, b = base+1; Ausgabe: Quotient = base+2, Rest = base+3
+                // Es wird zur Null hin gerundet, der Rest hat das Vorzeichen von a.
+                // Bei b = 0 ist der Quotient 0 und der Rest a.
+                let (a, b, q, r) = (base, base + 1, base + 2, base + 3);
+                let (d, t, m, x) = (base + 4, base + 5, base + 6, base + 7);
+
+                let l_zero = state.gen_label();
+                let l_outer = state.gen_label();
+                let l_inner = state.gen_label();
+                let l_sub = state.gen_label();
+                let l_signs = state.gen_label();
+                let l_aneg = state.gen_label();
+                let l_negq = state.gen_label();
+                let l_ret = state.gen_label();
+
+                state.adds(format!("label {}", self.label()));
+                state.adds(format!("lda {}", b));
+                state.adds(format!("jz {}", l_zero));
+
+                // d = |b|, r = |a|
+                let l_bpos = state.gen_label();
+                state.adds(format!("jp {}", l_bpos));
+                state.add("neg");
+                state.adds(format!("label {}", l_bpos));
+                state.adds(format!("sta {}", d));
+
+                let l_apos = state.gen_label();
+                state.adds(format!("lda {}", a));
+                state.adds(format!("jp {}", l_apos));
+                state.add("neg");
+                state.adds(format!("label {}", l_apos));
+                state.adds(format!("sta {}", r));
+                state.add("mka 0");
+                state.adds(format!("sta {}", q));
+
+                // Solange r >= d: größtes t = d * 2^k <= r suchen und abziehen
+                state.adds(format!("label {}", l_outer));
+                state.adds(format!("lda {}", d));
+                state.add("neg");
+                state.adds(format!("add {}", r));
+                state.adds(format!("jn {}", l_signs));
+                state.adds(format!("lda {}", d));
+                state.adds(format!("sta {}", t));
+                state.add("mka 1");
+                state.adds(format!("sta {}", m));
+
+                // r - t - t < 0 statt r < 2t, damit nichts überläuft
+                state.adds(format!("label {}", l_inner));
+                state.adds(format!("lda {}", t));
+                state.add("neg");
+                state.adds(format!("sta {}", x));
+                state.adds(format!("add {}", r));
+                state.adds(format!("add {}", x));
+                state.adds(format!("jn {}", l_sub));
+                state.adds(format!("lda {}", t));
+                state.adds(format!("add {}", t));
+                state.adds(format!("sta {}", t));
+                state.adds(format!("lda {}", m));
+                state.adds(format!("add {}", m));
+                state.adds(format!("sta {}", m));
+                state.adds(format!("jmp {}", l_inner));
+
+                state.adds(format!("label {}", l_sub));
+                state.adds(format!("lda {}", x));
+                state.adds(format!("add {}", r));
+                state.adds(format!("sta {}", r));
+                state.adds(format!("lda {}", q));
+                state.adds(format!("add {}", m));
+                state.adds(format!("sta {}", q));
+                state.adds(format!("jmp {}", l_outer));
+
+                // Vorzeichen anpassen
+                state.adds(format!("label {}", l_signs));
+                state.adds(format!("lda {}", a));
+                state.adds(format!("jn {}", l_aneg));
+                state.adds(format!("lda {}", b));
+                state.adds(format!("jn {}", l_negq));
+                state.add("ret");
+
+                state.adds(format!("label {}", l_aneg));
+                state.adds(format!("lda {}", r));
+                state.add("neg");
+                state.adds(format!("sta {}", r));
+                state.adds(format!("lda {}", b));
+                state.adds(format!("jn {}", l_ret));
+
+                state.adds(format!("label {}", l_negq));
+                state.adds(format!("lda {}", q));
+                state.add("neg");
+                state.adds(format!("sta {}", q));
+                state.adds(format!("label {}", l_ret));
+                state.add("ret");
 
-        for x in del {
-            self.dealloc_name(&x);
+                state.adds(format!("label {}", l_zero));
+                state.add("mka 0");
+                state.adds(format!("sta {}", q));
+                state.adds(format!("lda {}", a));
+                state.adds(format!("sta {}", r));
+                state.add("ret");
+            },
         }
     }
 }
 
+#[derive(Debug, Clone)]
+struct Constant {
+    name: String,
+    value: isize,
+    info: SourceInfo,
+    scope: usize,
+}
+
 struct FuncSign {
     nargs: usize,
     aargs: usize,
+    // Parameter und lokale Variablen liegen in einem Stack-Frame, Rekursion ist erlaubt
+    stack: bool,
+    info: SourceInfo,
+}
+
+// Ort eines Werts: feste Adresse oder Offset zum Frame-Pointer
+#[derive(Debug, Clone, Copy)]
+enum Loc {
+    Abs(usize),
+    Frame(usize),
+}
+
+impl Loc {
+    fn offset(&self, i: usize) -> Loc {
+        return match self {
+            Loc::Abs(a) => Loc::Abs(a + i),
+            Loc::Frame(o) => Loc::Frame(o + i),
+        };
+    }
+}
+
+// Feste Speicherzellen für die Aufrufkonvention mit Stack-Frames
+#[derive(Debug, Clone, Copy)]
+struct StackCells {
+    sp: usize,
+    fp: usize,
+    // Zwischenspeicher für den Akku und die berechnete Adresse
+    tmp: usize,
+    ptr: usize,
+    ret: usize,
+    // Kopien von Frame-Werten für Operationen, die eine feste Adresse brauchen
+    ops: [usize; 2],
+}
+
+// Speicher der Funktion, die gerade erzeugt wird, Adressen sind Offsets zum Frame-Pointer
+struct Frame {
+    tracker: MemoryTracker,
+    // Hier liegt der Frame-Pointer des Aufrufers
+    fp_slot: usize,
 }
 
 struct CodeGenState {
@@ -168,18 +484,269 @@ struct CodeGenState {
     code: String,
     labels: HashSet<u16>,
     functions: HashMap<String, FuncSign>,
+    // Leer im Hauptprogramm
     curr_func: String,
+    calls: CallGraph,
+    // (Ziel von continue, Ende) der umgebenden Schleifen, die innerste zuletzt
+    loop_labels: Vec<(String, String)>,
+    // Benutzte Laufzeitroutinen mit der Adresse ihrer Speicherzellen
+    runtime: BTreeMap<Runtime, usize>,
+    // Konstanten belegen keinen Speicher, Scopes wie beim MemoryTracker
+    constants: Vec<Constant>,
+    // Alle Funktionen benutzen Stack-Frames (--stack-frames)
+    stack_frames: bool,
+    stack: Option<StackCells>,
+    frame: Option<Frame>,
 }
 
 impl CodeGenState {
-    fn new() -> CodeGenState { return CodeGenState {
+    fn new(stack_frames: bool) -> CodeGenState { return CodeGenState {
         tracker: MemoryTracker::new(),
         code: String::new(),
         labels: HashSet::new(),
         functions: HashMap::new(),
         curr_func: String::new(),
+        calls: CallGraph::new(),
+        loop_labels: Vec::new(),
+        runtime: BTreeMap::new(),
+        constants: Vec::new(),
+        stack_frames: stack_frames,
+        stack: None,
+        frame: None,
     }; }
 
+    fn inc_scope(&mut self) {
+        self.active_tracker().inc_scope();
+        for c in &mut self.constants {
+            c.scope += 1;
+        }
+    }
+
+    fn dec_scope(&mut self) {
+        self.active_tracker().dec_scope();
+        self.constants.retain(|c| c.scope > 0);
+        for c in &mut self.constants {
+            c.scope -= 1;
+        }
+    }
+
+    fn get_const(&self, name: &String) -> Option<&Constant> {
+        return self.constants.iter().rev().find(|c| &c.name == name);
+    }
+
+    fn check_assignable(&self, name: &String, info: &SourceInfo) -> Result<(), Diagnostic> {
+        if let Some(c) = self.get_const(name) {
+            return Err(Diagnostic::error(format!("Cannot assign to constant \"{}\"", name), info.clone())
+                .with_label(c.info.clone(), "constant declared here".to_string()));
+        }
+        return Ok(());
+    }
+
+    // Konstanten werden vor Variablen gesucht, eine Variable mit demselben Namen wäre nie erreichbar
+    fn check_declarable(&self, name: &String, info: &SourceInfo) -> Result<(), Diagnostic> {
+        if let Some(c) = self.get_const(name) {
+            return Err(Diagnostic::error(format!("\"{}\" is already declared as a constant", name), info.clone())
+                .with_label(c.info.clone(), "constant declared here".to_string()));
+        }
+        return Ok(());
+    }
+
+    // In einer Funktion mit Stack-Frame wird im Frame allokiert
+    fn active_tracker(&mut self) -> &mut MemoryTracker {
+        return match &mut self.frame {
+            Some(f) => &mut f.tracker,
+            None => &mut self.tracker,
+        };
+    }
+
+    fn loc(&self, addr: usize) -> Loc {
+        return match self.frame {
+            Some(_) => Loc::Frame(addr),
+            None => Loc::Abs(addr),
+        };
+    }
+
+    fn alloc_var(&mut self, name: &str, info: &SourceInfo) {
+        self.active_tracker().alloc(name, Some(info.clone()));
+    }
+
+    fn alloc_array(&mut self, name: &str, len: usize, info: &SourceInfo) {
+        self.active_tracker().alloc_array(name, len, info.clone());
+    }
+
+    fn alloc_temp(&mut self) -> Loc {
+        let addr = self.active_tracker().alloc_temp();
+        return self.loc(addr);
+    }
+
+    fn dealloc_temp(&mut self, loc: Loc) {
+        let (Loc::Abs(addr) | Loc::Frame(addr)) = loc;
+        self.active_tracker().dealloc_addr(addr);
+    }
+
+    // Erst die lokalen Variablen im Frame, dann die globalen
+    fn lookup(&self, name: &String) -> Option<(Loc, &MemoryAllocation)> {
+        if let Some(f) = &self.frame {
+            if let Some(m) = f.tracker.find(name) {
+                return Some((Loc::Frame(m.start), m));
+            }
+        }
+        return self.tracker.find(name).map(|m| (Loc::Abs(m.start), m));
+    }
+
+    fn get(&self, name: &String, info: &SourceInfo) -> Result<Loc, Diagnostic> {
+        return match self.lookup(name) {
+            Some((loc, _)) => Ok(loc),
+            None => Err(Diagnostic::error(format!("Variable not found: \"{}\"", name), info.clone())),
+        };
+    }
+
+    // Wie get, aber der Name muss mit "arr" deklariert sein
+    fn get_array(&self, name: &String, info: &SourceInfo) -> Result<Loc, Diagnostic> {
+        let (loc, m) = match self.lookup(name) {
+            Some(val) => val,
+            None => return Err(Diagnostic::error(format!("Array not found: \"{}\"", name), info.clone())),
+        };
+        if !m.array {
+            let mut d = Diagnostic::error(format!("\"{}\" is not an array", name), info.clone());
+            if let Some(decl) = &m.info {
+                d = d.with_label(decl.clone(), "variable declared here".to_string());
+            }
+            return Err(d.with_note("only names declared with \"arr\" can be indexed".to_string()));
+        }
+        return Ok(loc);
+    }
+
+    // Gibt die Zellen der Aufrufkonvention zurück, beim ersten Aufruf werden sie angelegt
+    fn use_stack(&mut self) -> StackCells {
+        if let Some(s) = self.stack {
+            return s;
+        }
+        let base = self.tracker.alloc_static("$stack", 7);
+        let s = StackCells { sp: base, fp: base + 1, tmp: base + 2, ptr: base + 3, ret: base + 4, ops: [base + 5, base + 6] };
+        self.stack = Some(s);
+        return s;
+    }
+
+    fn stack_cells(&self) -> StackCells {
+        return self.stack.expect("stack frame without stack cells");
+    }
+
+    // ptr = FP + o
+    fn frame_addr(&mut self, o: usize) {
+        let s = self.stack_cells();
+        self.adds(format!("mka {}", o));
+        self.adds(format!("add {}", s.fp));
+        self.adds(format!("sta {}", s.ptr));
+    }
+
+    fn load(&mut self, loc: Loc) {
+        match loc {
+            Loc::Abs(a) => self.adds(format!("lda {}", a)),
+            Loc::Frame(o) => {
+                self.frame_addr(o);
+                self.adds(format!("ldad {}", self.stack_cells().ptr));
+            },
+        }
+    }
+
+    // Der Akku bleibt erhalten
+    fn store(&mut self, loc: Loc) {
+        match loc {
+            Loc::Abs(a) => self.adds(format!("sta {}", a)),
+            Loc::Frame(o) => {
+                let s = self.stack_cells();
+                self.adds(format!("sta {}", s.tmp));
+                self.frame_addr(o);
+                self.adds(format!("lda {}", s.tmp));
+                self.adds(format!("stad {}", s.ptr));
+            },
+        }
+    }
+
+    fn add_from(&mut self, loc: Loc) {
+        match loc {
+            Loc::Abs(a) => self.adds(format!("add {}", a)),
+            Loc::Frame(_) => {
+                let s = self.stack_cells();
+                self.adds(format!("sta {}", s.tmp));
+                self.load(loc);
+                self.adds(format!("add {}", s.tmp));
+            },
+        }
+    }
+
+    fn load_addr(&mut self, loc: Loc) {
+        match loc {
+            Loc::Abs(a) => self.adds(format!("mka {}", a)),
+            Loc::Frame(o) => {
+                self.adds(format!("mka {}", o));
+                self.adds(format!("add {}", self.stack_cells().fp));
+            },
+        }
+    }
+
+    // Akku = mem[mem[loc]]
+    fn load_indirect(&mut self, loc: Loc) {
+        match loc {
+            Loc::Abs(a) => self.adds(format!("ldad {}", a)),
+            Loc::Frame(_) => {
+                let s = self.stack_cells();
+                self.load(loc);
+                self.adds(format!("sta {}", s.ptr));
+                self.adds(format!("ldad {}", s.ptr));
+            },
+        }
+    }
+
+    // mem[mem[loc]] = Akku
+    fn store_indirect(&mut self, loc: Loc) {
+        match loc {
+            Loc::Abs(a) => self.adds(format!("stad {}", a)),
+            Loc::Frame(_) => {
+                let s = self.stack_cells();
+                self.adds(format!("sta {}", s.tmp));
+                self.load(loc);
+                self.adds(format!("sta {}", s.ptr));
+                self.adds(format!("lda {}", s.tmp));
+                self.adds(format!("stad {}", s.ptr));
+            },
+        }
+    }
+
+    // Feste Adresse mit dem Wert von loc, Werte im Frame werden in eine der Zellen ops[i] kopiert
+    fn operand(&mut self, loc: Loc, i: usize) -> usize {
+        return match loc {
+            Loc::Abs(a) => a,
+            Loc::Frame(_) => {
+                let cell = self.stack_cells().ops[i];
+                self.load(loc);
+                self.adds(format!("sta {}", cell));
+                cell
+            },
+        };
+    }
+
+    // Rücksprung, bei Stack-Frames wird vorher der Frame des Aufrufers wiederhergestellt
+    fn gen_return(&mut self) {
+        let fp_slot = match &self.frame {
+            Some(f) => f.fp_slot,
+            None => {
+                self.add("ret");
+                return;
+            },
+        };
+        let s = self.stack_cells();
+        self.adds(format!("sta {}", s.ret));
+        self.adds(format!("lda {}", s.fp));
+        self.adds(format!("sta {}", s.sp));
+        self.frame_addr(fp_slot);
+        self.adds(format!("ldad {}", s.ptr));
+        self.adds(format!("sta {}", s.fp));
+        self.adds(format!("lda {}", s.ret));
+        self.add("ret");
+    }
+
     fn add(&mut self, t: &str) {
         self.code.push_str(t);
         self.code.push('\n');
@@ -188,6 +755,30 @@ impl CodeGenState {
         self.add(t.as_str());
     }
 
+    // Gibt die Speicherzellen der Routine zurück, beim ersten Aufruf werden sie angelegt
+    fn use_runtime(&mut self, r: Runtime) -> usize {
+        if let Some(base) = self.runtime.get(&r) {
+            return *base;
+        }
+        // "$" kann in keinem Bezeichner vorkommen
+        let base = self.tracker.alloc_static(&format!("${}", r.label()), r.cells());
+        self.runtime.insert(r, base);
+        return base;
+    }
+
+    fn gen_runtime(&mut self) {
+        if self.runtime.is_empty() {
+            return;
+        }
+
+        let skip_label = self.gen_label();
+        self.adds(format!("jmp {}", skip_label));
+        for (r, base) in self.runtime.clone() {
+            r.codegen(self, base);
+        }
+        self.adds(format!("label {}", skip_label));
+    }
+
     fn gen_label(&mut self) -> String {
         let mut num = rand::random::<u16>();
         while self.labels.contains(&num) {
@@ -205,25 +796,59 @@ struct BlockStatement {
 }
 
 impl Statement for BlockStatement {
-    fn codegen(&self, state: &mut CodeGenState) {
-        state.tracker.inc_scope();
-        for s in &self.statements {
-            s.codegen(state);
-        }
-        state.tracker.dec_scope();
+    fn codegen(&self, state: &mut CodeGenState) -> Result<(), Diagnostic> {
+        state.inc_scope();
+        // Scope wird auch bei einem Fehler verlassen, damit es keine Folgefehler gibt
+        let res = self.statements.iter().try_for_each(|s| s.codegen(state));
+        state.dec_scope();
+        return res;
+    }
+
+    fn declare(&self, state: &mut CodeGenState) -> Result<(), Diagnostic> {
+        return self.statements.iter().try_for_each(|s| s.declare(state));
+    }
+}
+
+
+// Mehrere Anweisungen ohne eigenen Scope, z.B. "var a = 1, b"
+#[derive(Debug)]
+struct StatementList {
+    statements: Vec<Box<dyn Statement>>,
+}
+
+impl Statement for StatementList {
+    fn codegen(&self, state: &mut CodeGenState) -> Result<(), Diagnostic> {
+        return self.statements.iter().try_for_each(|s| s.codegen(state));
+    }
+
+    fn declare(&self, state: &mut CodeGenState) -> Result<(), Diagnostic> {
+        return self.statements.iter().try_for_each(|s| s.declare(state));
     }
 }
 
 
 #[derive(Debug)]
 struct VarDeclaration {
-    varname: String
+    varname: String,
+    // Wird vor der Deklaration ausgewertet und sieht daher noch eine äußere Variable
+    // mit demselben Namen (Startwert der for-Schleife)
+    value: Option<Box<dyn Expression>>,
+    info: SourceInfo,
 }
 
 impl Statement for VarDeclaration {
-    fn codegen(&self, state: &mut CodeGenState) {
-        state.tracker.alloc(&self.varname);
+    fn codegen(&self, state: &mut CodeGenState) -> Result<(), Diagnostic> {
+        if let Some(v) = &self.value {
+            v.codegen(state)?;
+        }
+        state.check_declarable(&self.varname, &self.info)?;
+        state.alloc_var(&self.varname, &self.info);
         //state.adds(format!("# VarDeclaration \"{}\"", &self.varname));
+        if self.value.is_some() {
+            let loc = state.get(&self.varname, &self.info)?;
+            state.store(loc);
+        }
+        return Ok(());
     }
 }
 
@@ -232,13 +857,17 @@ impl Statement for VarDeclaration {
 struct VarAssignment {
     varname: String,
     value: Box<dyn Expression>,
+    info: SourceInfo,
 }
 
 impl Statement for VarAssignment {
-    fn codegen(&self, state: &mut CodeGenState) {
+    fn codegen(&self, state: &mut CodeGenState) -> Result<(), Diagnostic> {
         //state.add("# VarAssignment");
-        self.value.codegen(state);
-        state.adds(format!("sta {}", state.tracker.get(&self.varname)));
+        state.check_assignable(&self.varname, &self.info)?;
+        self.value.codegen(state)?;
+        let loc = state.get(&self.varname, &self.info)?;
+        state.store(loc);
+        return Ok(());
     }
 }
 
@@ -250,17 +879,18 @@ struct DerefAssignment {
 }
 
 impl Statement for DerefAssignment {
-    fn codegen(&self, state: &mut CodeGenState) {
+    fn codegen(&self, state: &mut CodeGenState) -> Result<(), Diagnostic> {
         // Address
-        self.addr.codegen(state);
-        let addr_addr = state.tracker.alloc_temp();
-        state.adds(format!("sta {}", addr_addr));
+        self.addr.codegen(state)?;
+        let addr_addr = state.alloc_temp();
+        state.store(addr_addr);
 
         // Value
-        self.value.codegen(state);
-        state.adds(format!("stad {}", addr_addr));
+        self.value.codegen(state)?;
+        state.store_indirect(addr_addr);
 
-        state.tracker.dealloc_addr(addr_addr);
+        state.dealloc_temp(addr_addr);
+        return Ok(());
     }
 }
 
@@ -268,13 +898,55 @@ impl Statement for DerefAssignment {
 #[derive(Debug)]
 struct ArrDeclaration {
     arrname: String,
-    arrlen: usize,
+    // Ohne Länge wird die Anzahl der Startwerte genommen
+    arrlen: Option<Box<dyn Expression>>,
+    values: Vec<Box<dyn Expression>>,
+    info: SourceInfo,
 }
 
 impl Statement for ArrDeclaration {
-    fn codegen(&self, state: &mut CodeGenState) {
-        state.tracker.alloc_array(&self.arrname, self.arrlen);
+    fn codegen(&self, state: &mut CodeGenState) -> Result<(), Diagnostic> {
+        let len = match &self.arrlen {
+            Some(e) => match e.static_eval(state) {
+                Some(l) => l,
+                None => return Err(Diagnostic::error("Array length must be a constant expression".to_string(), self.info.clone())),
+            },
+            None => self.values.len() as isize,
+        };
+        if len > WORD_MAX {
+            return Err(Diagnostic::error(format!("Length of array \"{}\" out of range", self.arrname), self.info.clone())
+                .with_note(format!("arrays can have at most {} elements", WORD_MAX)));
+        }
+        if len <= 0 {
+            return Err(Diagnostic::error(format!("Invalid length {} for array \"{}\"", len, self.arrname), self.info.clone())
+                .with_note("arrays must have at least one element".to_string()));
+        }
+        let len = len as usize;
+        if self.values.len() > len {
+            return Err(Diagnostic::error(format!("Too many values for array \"{}\"", self.arrname), self.info.clone())
+                .with_note(format!("expected at most {}, found {}", len, self.values.len())));
+        }
+
+        state.check_declarable(&self.arrname, &self.info)?;
+        state.alloc_array(&self.arrname, len, &self.info);
         //return format!("# ArrDeclaration \"{}\" len: {}\n", &self.arrname, &self.arrlen);
+
+        if self.values.is_empty() {
+            return Ok(());
+        }
+        let arr = state.get(&self.arrname, &self.info)?;
+        for (i, v) in self.values.iter().enumerate() {
+            v.codegen(state)?;
+            state.store(arr.offset(i));
+        }
+        // Mit Startwerten wird der Rest mit 0 gefüllt
+        if self.values.len() < len {
+            state.add("mka 0");
+            for i in self.values.len()..len {
+                state.store(arr.offset(i));
+            }
+        }
+        return Ok(());
     }
 }
 
@@ -284,30 +956,35 @@ struct ArrAssignment {
     arrname: String,
     index: Box<dyn Expression>,
     value: Box<dyn Expression>,
+    info: SourceInfo,
 }
 
 impl Statement for ArrAssignment {
-    fn codegen(&self, state: &mut CodeGenState) {
+    fn codegen(&self, state: &mut CodeGenState) -> Result<(), Diagnostic> {
+        state.check_assignable(&self.arrname, &self.info)?;
+
         // Store value
-        self.value.codegen(state);
-        let value_addr = state.tracker.alloc_temp();
-        state.adds(format!("sta {}", value_addr));
+        self.value.codegen(state)?;
+        let value_addr = state.alloc_temp();
+        state.store(value_addr);
 
         // Store index
-        self.index.codegen(state);
-        let index_addr = state.tracker.alloc_temp();
-        state.adds(format!("sta {}", index_addr));
+        self.index.codegen(state)?;
+        let index_addr = state.alloc_temp();
+        state.store(index_addr);
         // Add index to address of array
-        state.adds(format!("mka {}", state.tracker.get(&self.arrname)));
-        state.adds(format!("add {}", index_addr));
-        state.adds(format!("sta {}", index_addr));
+        let arr = state.get_array(&self.arrname, &self.info)?;
+        state.load_addr(arr);
+        state.add_from(index_addr);
+        state.store(index_addr);
 
         // Load and store value at index
-        state.adds(format!("lda {}", value_addr));
-        state.adds(format!("stad {}", index_addr));
+        state.load(value_addr);
+        state.store_indirect(index_addr);
 
-        state.tracker.dealloc_addr(value_addr);
-        state.tracker.dealloc_addr(index_addr);
+        state.dealloc_temp(value_addr);
+        state.dealloc_temp(index_addr);
+        return Ok(());
     }
 }
 
@@ -320,16 +997,60 @@ struct BinaryOperator {
 }
 
 impl Expression for BinaryOperator {
-    fn codegen(&self, state: &mut CodeGenState) {
+    fn codegen(&self, state: &mut CodeGenState) -> Result<(), Diagnostic> {
         //state.adds(format!("BinaryOperation: {:?}", self.operator));
 
-        let addr_a = state.tracker.alloc_temp();
-        let addr_b = state.tracker.alloc_temp();
+        // Kurzschlussauswertung: rhs wird nur berechnet, wenn es das Ergebnis noch ändern kann
+        match self.operator {
+            BinaryOperatorType::And => {
+                let l_false = state.gen_label();
+                let l_end = state.gen_label();
 
-        self.lhs.codegen(state);
-        state.adds(format!("sta {}", addr_a));
-        self.rhs.codegen(state);
-        state.adds(format!("sta {}", addr_b));
+                self.lhs.codegen(state)?;
+                state.adds(format!("jz {}", l_false));
+                self.rhs.codegen(state)?;
+                state.adds(format!("jz {}", l_false));
+
+                state.add("mka 1");
+                state.adds(format!("jmp {}", l_end));
+                state.adds(format!("label {}", l_false));
+                state.add("mka 0");
+                state.adds(format!("label {}", l_end));
+                return Ok(());
+            },
+            BinaryOperatorType::Or => {
+                let l_rhs = state.gen_label();
+                let l_true = state.gen_label();
+                let l_false = state.gen_label();
+                let l_end = state.gen_label();
+
+                self.lhs.codegen(state)?;
+                state.adds(format!("jz {}", l_rhs));
+                state.adds(format!("jmp {}", l_true));
+                state.adds(format!("label {}", l_rhs));
+                self.rhs.codegen(state)?;
+                state.adds(format!("jz {}", l_false));
+
+                state.adds(format!("label {}", l_true));
+                state.add("mka 1");
+                state.adds(format!("jmp {}", l_end));
+                state.adds(format!("label {}", l_false));
+                state.add("mka 0");
+                state.adds(format!("label {}", l_end));
+                return Ok(());
+            },
+            _ => {},
+        }
+
+        let loc_a = state.alloc_temp();
+        let loc_b = state.alloc_temp();
+
+        self.lhs.codegen(state)?;
+        state.store(loc_a);
+        self.rhs.codegen(state)?;
+        state.store(loc_b);
+        let addr_a = state.operand(loc_a, 0);
+        let addr_b = state.operand(loc_b, 1);
 
         match self.operator {
             BinaryOperatorType::Plus => {
@@ -345,29 +1066,25 @@ impl Expression for BinaryOperator {
                 state.adds(format!("add {}", addr_b));
             },
             BinaryOperatorType::Times => {
-                let addr_result = state.tracker.alloc_temp();
-                state.add("mka 0");
-                state.adds(format!("sta {}", addr_result));
-
-                let loop_label = state.gen_label();
-                state.adds(format!("label {}", loop_label));
-
-                state.add("mka -1");
-                state.adds(format!("add {}", addr_a));
-                state.adds(format!("sta {}", addr_a));
-
-                state.adds(format!("lda {}", addr_result));
-                state.adds(format!("add {}", addr_b));
-                state.adds(format!("sta {}", addr_result));
-
+                let base = state.use_runtime(Runtime::Mul);
                 state.adds(format!("lda {}", addr_a));
-                let end_label = state.gen_label();
-                state.adds(format!("jz {}", end_label));
-                state.adds(format!("jmp {}", loop_label));
-                state.adds(format!("label {}", end_label));
-                state.adds(format!("lda {}", addr_result));
-
-                state.tracker.dealloc_addr(addr_result);
+                state.adds(format!("sta {}", base));
+                state.adds(format!("lda {}", addr_b));
+                state.adds(format!("sta {}", base + 1));
+                state.adds(format!("call {}", Runtime::Mul.label()));
+                state.adds(format!("lda {}", base + 2));
+            },
+            BinaryOperatorType::Divide | BinaryOperatorType::Modulo => {
+                let base = state.use_runtime(Runtime::DivMod);
+                state.adds(format!("lda {}", addr_a));
+                state.adds(format!("sta {}", base));
+                state.adds(format!("lda {}", addr_b));
+                state.adds(format!("sta {}", base + 1));
+                state.adds(format!("call {}", Runtime::DivMod.label()));
+                match self.operator {
+                    BinaryOperatorType::Divide => state.adds(format!("lda {}", base + 2)),
+                    _ => state.adds(format!("lda {}", base + 3)),
+                }
             },
             BinaryOperatorType::Equals => {
                 state.adds(format!("lda {}", addr_a));
@@ -452,10 +1169,63 @@ impl Expression for BinaryOperator {
 
                 state.adds(format!("label {}", l_end));
             },
+            BinaryOperatorType::LessEquals | BinaryOperatorType::GreaterEquals => {
+                // a - b, bei 0 sind beide wahr
+                state.adds(format!("lda {}", addr_b));
+                state.add("neg");
+                state.adds(format!("sta {}", addr_b));
+
+                state.adds(format!("lda {}", addr_a));
+                state.adds(format!("add {}", addr_b));
+
+                let l_a = state.gen_label();
+                let l_end = state.gen_label();
+
+                state.adds(format!("jz {}", l_a));
+                match self.operator {
+                    BinaryOperatorType::LessEquals => state.adds(format!("jn {}", l_a)),
+                    _ => state.adds(format!("jp {}", l_a)),
+                }
+
+                state.add("mka 0");
+                state.adds(format!("jmp {}", l_end));
+
+                state.adds(format!("label {}", l_a));
+                state.add("mka 1");
+
+                state.adds(format!("label {}", l_end));
+            },
+            BinaryOperatorType::And | BinaryOperatorType::Or => unreachable!(),
         }
 
-        state.tracker.dealloc_addr(addr_a);
-        state.tracker.dealloc_addr(addr_b);
+        state.dealloc_temp(loc_a);
+        state.dealloc_temp(loc_b);
+        return Ok(());
+    }
+
+    // Gleiche Semantik wie der erzeugte Code, auch bei Division durch 0
+    fn static_eval(&self, state: &CodeGenState) -> Option<isize> {
+        let a = self.lhs.static_eval(state)?;
+        let b = self.rhs.static_eval(state)?;
+        if a == OVERFLOW || b == OVERFLOW {
+            return Some(OVERFLOW);
+        }
+        let res = match self.operator {
+            BinaryOperatorType::Plus => a.checked_add(b),
+            BinaryOperatorType::Minus => a.checked_sub(b),
+            BinaryOperatorType::Times => a.checked_mul(b),
+            BinaryOperatorType::Divide => if b == 0 { Some(0) } else { a.checked_div(b) },
+            BinaryOperatorType::Modulo => if b == 0 { Some(a) } else { a.checked_rem(b) },
+            BinaryOperatorType::LessThan => Some((a < b) as isize),
+            BinaryOperatorType::GreaterThan => Some((a > b) as isize),
+            BinaryOperatorType::LessEquals => Some((a <= b) as isize),
+            BinaryOperatorType::GreaterEquals => Some((a >= b) as isize),
+            BinaryOperatorType::Equals => Some((a == b) as isize),
+            BinaryOperatorType::NotEquals => Some((a != b) as isize),
+            BinaryOperatorType::And => Some((a != 0 && b != 0) as isize),
+            BinaryOperatorType::Or => Some((a != 0 || b != 0) as isize),
+        };
+        return Some(res.unwrap_or(OVERFLOW));
     }
 }
 
@@ -467,22 +1237,48 @@ struct UnaryOperator {
 }
 
 impl Expression for UnaryOperator {
-    fn codegen(&self, state: &mut CodeGenState) {
-        let addr = state.tracker.alloc_temp();
-        self.val.codegen(state);
-        state.adds(format!("sta {}", addr));
+    fn codegen(&self, state: &mut CodeGenState) -> Result<(), Diagnostic> {
+        let loc = state.alloc_temp();
+        self.val.codegen(state)?;
+        state.store(loc);
+        let addr = state.operand(loc, 0);
 
         match self.operator {
             UnaryOperatorType::Negation => {
                 state.adds(format!("lda {}", addr));
                 state.add("neg");
             },
+            UnaryOperatorType::Not => {
+                state.adds(format!("lda {}", addr));
+
+                let l_zero = state.gen_label();
+                let l_end = state.gen_label();
+                state.adds(format!("jz {}", l_zero));
+                state.add("mka 0");
+                state.adds(format!("jmp {}", l_end));
+                state.adds(format!("label {}", l_zero));
+                state.add("mka 1");
+                state.adds(format!("label {}", l_end));
+            },
             UnaryOperatorType::Deref => {
                 state.adds(format!("ldad {}", addr));
             },
         }
 
-        state.tracker.dealloc_addr(addr);
+        state.dealloc_temp(loc);
+        return Ok(());
+    }
+
+    fn static_eval(&self, state: &CodeGenState) -> Option<isize> {
+        let val = self.val.static_eval(state)?;
+        if val == OVERFLOW {
+            return Some(OVERFLOW);
+        }
+        return match self.operator {
+            UnaryOperatorType::Negation => Some(val.checked_neg().unwrap_or(OVERFLOW)),
+            UnaryOperatorType::Not => Some((val == 0) as isize),
+            UnaryOperatorType::Deref => None,
+        };
     }
 }
 
@@ -493,9 +1289,14 @@ struct Number {
 }
 
 impl Expression for Number {
-    fn codegen(&self, state: &mut CodeGenState) {
+    fn codegen(&self, state: &mut CodeGenState) -> Result<(), Diagnostic> {
         //state.adds(format!("# Number {}", self.num));
         state.adds(format!("mka {}", self.num));
+        return Ok(());
+    }
+
+    fn static_eval(&self, _state: &CodeGenState) -> Option<isize> {
+        return Some(self.num);
     }
 }
 
@@ -503,12 +1304,50 @@ impl Expression for Number {
 #[derive(Debug)]
 struct Variable {
     varname: String,
+    info: SourceInfo,
 }
 
 impl Expression for Variable {
-    fn codegen(&self, state: &mut CodeGenState) {
-        let val = state.tracker.get(&self.varname);
-        state.adds(format!("lda {}", val));
+    fn codegen(&self, state: &mut CodeGenState) -> Result<(), Diagnostic> {
+        if let Some(c) = state.get_const(&self.varname) {
+            state.adds(format!("mka {}", c.value));
+            return Ok(());
+        }
+        let loc = state.get(&self.varname, &self.info)?;
+        state.load(loc);
+        return Ok(());
+    }
+
+    fn static_eval(&self, state: &CodeGenState) -> Option<isize> {
+        return state.get_const(&self.varname).map(|c| c.value);
+    }
+}
+
+
+#[derive(Debug)]
+struct ConstDeclaration {
+    name: String,
+    value: Box<dyn Expression>,
+    info: SourceInfo,
+}
+
+impl Statement for ConstDeclaration {
+    fn codegen(&self, state: &mut CodeGenState) -> Result<(), Diagnostic> {
+        // Eine Konstante aus einem äußeren Block darf verdeckt werden, eine aus demselben nicht
+        if let Some(c) = state.get_const(&self.name).filter(|c| c.scope == 0) {
+            return Err(Diagnostic::error(format!("\"{}\" is already declared as a constant", self.name), self.info.clone())
+                .with_label(c.info.clone(), "constant declared here".to_string()));
+        }
+        let value = match self.value.static_eval(state) {
+            Some(v) => v,
+            None => return Err(Diagnostic::error(format!("Value of constant \"{}\" must be a constant expression", self.name), self.info.clone())),
+        };
+        if !(-WORD_MAX..=WORD_MAX).contains(&value) {
+            return Err(Diagnostic::error(format!("Value of constant \"{}\" out of range", self.name), self.info.clone())
+                .with_note(format!("numbers must be between {} and {}", -WORD_MAX, WORD_MAX)));
+        }
+        state.constants.push(Constant { name: self.name.clone(), value: value, info: self.info.clone(), scope: 0 });
+        return Ok(());
     }
 }
 
@@ -517,21 +1356,24 @@ impl Expression for Variable {
 struct Array {
     arrname: String,
     index: Box<dyn Expression>,
+    info: SourceInfo,
 }
 
 impl Expression for Array {
-    fn codegen(&self, state: &mut CodeGenState) {
-        state.adds(format!("mka {}", state.tracker.get(&self.arrname)));
-        let tmp = state.tracker.alloc_temp();
-        state.adds(format!("sta {}", tmp));
+    fn codegen(&self, state: &mut CodeGenState) -> Result<(), Diagnostic> {
+        let arr = state.get_array(&self.arrname, &self.info)?;
+        state.load_addr(arr);
+        let tmp = state.alloc_temp();
+        state.store(tmp);
         
-        self.index.codegen(state);
+        self.index.codegen(state)?;
 
-        state.adds(format!("add {}", tmp));
-        state.adds(format!("sta {}", tmp));
-        state.adds(format!("ldad {}", tmp));
+        state.add_from(tmp);
+        state.store(tmp);
+        state.load_indirect(tmp);
 
-        state.tracker.dealloc_addr(tmp);
+        state.dealloc_temp(tmp);
+        return Ok(());
     }
 }
 
@@ -540,18 +1382,38 @@ impl Expression for Array {
 struct IfStatement {
     condition: Box<dyn Expression>,
     block: BlockStatement,
+    // Entweder ein BlockStatement oder ein weiteres IfStatement (else if)
+    else_block: Option<Box<dyn Statement>>,
 }
 
 impl Statement for IfStatement {
-    fn codegen(&self, state: &mut CodeGenState) {
-        self.condition.codegen(state);
+    fn codegen(&self, state: &mut CodeGenState) -> Result<(), Diagnostic> {
+        self.condition.codegen(state)?;
 
         let label = state.gen_label();
         state.adds(format!("jz {}", label));
 
-        self.block.codegen(state);
+        self.block.codegen(state)?;
+
+        if let Some(else_block) = &self.else_block {
+            // Nach dem if-Teil über die else-Kette springen
+            let end_label = state.gen_label();
+            state.adds(format!("jmp {}", end_label));
+            state.adds(format!("label {}", label));
+            else_block.codegen(state)?;
+            state.adds(format!("label {}", end_label));
+        } else {
+            state.adds(format!("label {}", label));
+        }
+        return Ok(());
+    }
 
-        state.adds(format!("label {}", label));
+    fn declare(&self, state: &mut CodeGenState) -> Result<(), Diagnostic> {
+        self.block.declare(state)?;
+        if let Some(else_block) = &self.else_block {
+            else_block.declare(state)?;
+        }
+        return Ok(());
     }
 }
 
@@ -560,22 +1422,76 @@ impl Statement for IfStatement {
 struct WhileLoop {
     condition: Box<dyn Expression>,
     block: BlockStatement,
+    // Wird nach jedem Durchlauf ausgeführt, auch nach "continue" (für for-Schleifen)
+    step: Option<Box<dyn Statement>>,
 }
 
 impl Statement for WhileLoop {
-    fn codegen(&self, state: &mut CodeGenState) {
+    fn codegen(&self, state: &mut CodeGenState) -> Result<(), Diagnostic> {
         let start_label = state.gen_label();
         state.adds(format!("label {}", start_label));
 
-        self.condition.codegen(state);
+        self.condition.codegen(state)?;
 
         let end_label = state.gen_label();
         state.adds(format!("jz {}", end_label));
 
-        self.block.codegen(state);
+        let continue_label = match self.step {
+            Some(_) => state.gen_label(),
+            None => start_label.clone(),
+        };
+
+        state.loop_labels.push((continue_label.clone(), end_label.clone()));
+        let res = self.block.codegen(state);
+        state.loop_labels.pop();
+        res?;
+
+        if let Some(step) = &self.step {
+            state.adds(format!("label {}", continue_label));
+            step.codegen(state)?;
+        }
 
         state.adds(format!("jmp {}", start_label));
         state.adds(format!("label {}", end_label));
+        return Ok(());
+    }
+
+    fn declare(&self, state: &mut CodeGenState) -> Result<(), Diagnostic> {
+        return self.block.declare(state);
+    }
+}
+
+
+#[derive(Debug)]
+struct BreakStatement {
+    info: SourceInfo,
+}
+
+impl Statement for BreakStatement {
+    fn codegen(&self, state: &mut CodeGenState) -> Result<(), Diagnostic> {
+        let end_label = match state.loop_labels.last() {
+            Some((_, end)) => end.clone(),
+            None => return Err(Diagnostic::error("\'break\' outside of a loop".to_string(), self.info.clone())),
+        };
+        state.adds(format!("jmp {}", end_label));
+        return Ok(());
+    }
+}
+
+
+#[derive(Debug)]
+struct ContinueStatement {
+    info: SourceInfo,
+}
+
+impl Statement for ContinueStatement {
+    fn codegen(&self, state: &mut CodeGenState) -> Result<(), Diagnostic> {
+        let start_label = match state.loop_labels.last() {
+            Some((start, _)) => start.clone(),
+            None => return Err(Diagnostic::error("\'continue\' outside of a loop".to_string(), self.info.clone())),
+        };
+        state.adds(format!("jmp {}", start_label));
+        return Ok(());
     }
 }
 
@@ -585,43 +1501,130 @@ struct FunctionDeclaration {
     name: String,
     body: BlockStatement,
     param_names: Vec<String>,
+    // "recursive func", sonst nur mit --stack-frames
+    recursive: bool,
+    info: SourceInfo,
 }
 
 impl Statement for FunctionDeclaration {
-    fn codegen(&self, state: &mut CodeGenState) {
-        state.curr_func = self.name.clone();
+    fn codegen(&self, state: &mut CodeGenState) -> Result<(), Diagnostic> {
+        for p in &self.param_names {
+            state.check_declarable(p, &self.info)?;
+        }
+        let outer_func = std::mem::replace(&mut state.curr_func, self.name.clone());
+        // Schleifen außerhalb der Funktion sind im Funktionskörper nicht erreichbar
+        let outer_loops = std::mem::take(&mut state.loop_labels);
+        // Lokale Variablen einer umgebenden Funktion mit Stack-Frame ebenfalls nicht
+        let outer_frame = state.frame.take();
 
         let skip_label = state.gen_label();
         state.adds(format!("jmp {}", skip_label));
         state.adds(format!("label func_{}", self.name));
 
-        if self.param_names.len() != 0 {
-            let fargs_addr = state.tracker.alloc_temp_array(self.param_names.len());
-            state.functions.insert(self.name.clone(), FuncSign { nargs: self.param_names.len(), aargs: fargs_addr });
+        let res = if self.recursive || state.stack_frames {
+            self.codegen_stack(state)
+        } else {
+            self.codegen_static(state)
+        };
+
+        state.adds(format!("label {}", skip_label));
 
-            // Alloc all the names
-            let mut a = fargs_addr;
-            for pname in &self.param_names {
-                state.tracker.alloc_overlay(pname, a);
-                a += 1;
-            }
+        state.curr_func = outer_func;
+        state.loop_labels = outer_loops;
+        state.frame = outer_frame;
+        return res;
+    }
+
+    // Parameterblock bzw. Stack-Zellen werden hier angelegt, damit die Funktion überall aufgerufen werden kann
+    fn declare(&self, state: &mut CodeGenState) -> Result<(), Diagnostic> {
+        if let Some(f) = state.functions.get(&self.name) {
+            return Err(Diagnostic::error(format!("Function \"{}\" is already declared", self.name), self.info.clone())
+                .with_label(f.info.clone(), "first declared here".to_string()));
+        }
+
+        let nargs = self.param_names.len();
+        let sign = if self.recursive || state.stack_frames {
+            state.use_stack();
+            FuncSign { nargs: nargs, aargs: 0, stack: true, info: self.info.clone() }
+        } else if nargs > 0 {
+            let aargs = state.tracker.alloc_static(&format!("$args_{}", self.name), nargs);
+            FuncSign { nargs: nargs, aargs: aargs, stack: false, info: self.info.clone() }
         } else {
-            state.functions.insert(self.name.clone(), FuncSign { nargs: 0, aargs: 0 });
+            FuncSign { nargs: 0, aargs: 0, stack: false, info: self.info.clone() }
+        };
+        state.calls.add_function(&self.name, sign.stack, self.info.clone());
+        state.functions.insert(self.name.clone(), sign);
+
+        return self.body.declare(state);
+    }
+}
+
+impl FunctionDeclaration {
+    // Parameter liegen in einem festen Block, den der Aufrufer beschreibt
+    fn codegen_static(&self, state: &mut CodeGenState) -> Result<(), Diagnostic> {
+        let mark = state.tracker.mark();
+        // Die Funktion kann schon vorher aufgerufen werden, ihr Speicher darf sich nicht
+        // mit dem des Aufrufers überschneiden
+        let outer_floor = std::mem::replace(&mut state.tracker.floor, state.tracker.size);
+        let fargs_addr = state.functions[&self.name].aargs;
+
+        // Alloc all the names
+        for (a, pname) in (fargs_addr..).zip(&self.param_names) {
+            state.tracker.alloc_overlay(pname, a);
         }
 
         // Code
-        self.body.codegen(state);
+        let res = self.body.codegen(state);
 
+        // Ohne "return" wird 0 zurückgegeben
+        state.add("mka 0");
         state.add("ret");
 
-        state.adds(format!("label {}", skip_label));
-
         // Dealloc all the names
         for p in self.param_names.clone() {
             state.tracker.dealloc_name(&p);
         }
+        state.tracker.keep_since(mark, &format!("$func_{}", self.name));
+        state.tracker.floor = outer_floor;
+        return res;
+    }
+
+    // Frame: Parameter (vom Aufrufer ab SP geschrieben), alter FP, lokale Variablen und Temporäre
+    fn codegen_stack(&self, state: &mut CodeGenState) -> Result<(), Diagnostic> {
+        let s = state.use_stack();
 
-        state.curr_func = String::new();
+        let mut tracker = MemoryTracker::new();
+        for pname in &self.param_names {
+            tracker.alloc(pname, None);
+        }
+        let fp_slot = tracker.alloc_temp();
+        state.frame = Some(Frame { tracker: tracker, fp_slot: fp_slot });
+
+        // FP des Aufrufers sichern, FP = SP, SP hinter den Frame setzen
+        state.adds(format!("lda {}", s.fp));
+        state.adds(format!("sta {}", s.tmp));
+        state.adds(format!("mka {}", fp_slot));
+        state.adds(format!("add {}", s.sp));
+        state.adds(format!("sta {}", s.ptr));
+        state.adds(format!("lda {}", s.tmp));
+        state.adds(format!("stad {}", s.ptr));
+        state.adds(format!("lda {}", s.sp));
+        state.adds(format!("sta {}", s.fp));
+        // Die Größe des Frames steht erst nach dem Funktionskörper fest
+        let size_placeholder = format!("mka ${}\n", state.gen_label());
+        state.code.push_str(&size_placeholder);
+        state.adds(format!("add {}", s.fp));
+        state.adds(format!("sta {}", s.sp));
+
+        // Code
+        let res = self.body.codegen(state);
+
+        state.add("mka 0");
+        state.gen_return();
+
+        let size = state.frame.as_ref().map_or(0, |f| f.tracker.size);
+        state.code = state.code.replace(&size_placeholder, &format!("mka {}\n", size));
+        return res;
     }
 }
 
@@ -630,31 +1633,70 @@ impl Statement for FunctionDeclaration {
 struct FunctionCall {
     name: String,
     params: Vec<Box<dyn Expression>>,
+    info: SourceInfo,
 }
 
 impl Expression for FunctionCall {
-    fn codegen(&self, state: &mut CodeGenState) {
+    fn codegen(&self, state: &mut CodeGenState) -> Result<(), Diagnostic> {
         let fun_sign = match state.functions.get(&self.name) {
             Some(val) => val,
-            None => {panic!("Function {} not found!", self.name)},
+            None => return Err(Diagnostic::error(format!("Function \"{}\" not found", self.name), self.info.clone())),
         };
 
-        if &self.name == &state.curr_func {
-            panic!("No recusion allowed!");
-        }
+        // Rekursion wird nach der Codegenerierung im Aufrufgraph gesucht
+        state.calls.add_call(&state.curr_func, &self.name, self.info.clone());
 
         if fun_sign.nargs != self.params.len() {
-            panic!("Invalid number of arguments!");
+            return Err(Diagnostic::error(format!("Invalid number of arguments for function \"{}\"", self.name), self.info.clone())
+                .with_label(fun_sign.info.clone(), "function declared here".to_string())
+                .with_note(format!("expected {}, found {}", fun_sign.nargs, self.params.len())));
         }
 
-        let mut a = fun_sign.aargs;
+        let (aargs, stack) = (fun_sign.aargs, fun_sign.stack);
+
+        // Erst alle Argumente berechnen, ein Aufruf in einem Argument würde
+        // sonst schon geschriebene Parameter überschreiben
+        let mut temps: Vec<Loc> = Vec::new();
         for p in &self.params {
-            p.codegen(state);
-            state.adds(format!("sta {}", a));
-            a += 1;
+            p.codegen(state)?;
+            let t = state.alloc_temp();
+            state.store(t);
+            temps.push(t);
         }
 
+        for (i, t) in temps.iter().enumerate() {
+            state.load(*t);
+            if stack {
+                // Parameter liegen am Anfang des neuen Frames ab SP
+                let s = state.stack_cells();
+                state.adds(format!("sta {}", s.tmp));
+                state.adds(format!("mka {}", i));
+                state.adds(format!("add {}", s.sp));
+                state.adds(format!("sta {}", s.ptr));
+                state.adds(format!("lda {}", s.tmp));
+                state.adds(format!("stad {}", s.ptr));
+            } else {
+                state.adds(format!("sta {}", aargs + i));
+            }
+        }
         state.adds(format!("call func_{}", self.name));
+
+        for t in temps {
+            state.dealloc_temp(t);
+        }
+        return Ok(());
+    }
+}
+
+
+#[derive(Debug)]
+struct CallStatement {
+    call: FunctionCall,
+}
+
+impl Statement for CallStatement {
+    fn codegen(&self, state: &mut CodeGenState) -> Result<(), Diagnostic> {
+        return self.call.codegen(state);
     }
 }
 
@@ -665,9 +1707,10 @@ struct ReturnStatement {
 }
 
 impl Statement for ReturnStatement {
-    fn codegen(&self, state: &mut CodeGenState) {
-        self.value.codegen(state);
-        state.add("ret");
+    fn codegen(&self, state: &mut CodeGenState) -> Result<(), Diagnostic> {
+        self.value.codegen(state)?;
+        state.gen_return();
+        return Ok(());
     }
 }
 
@@ -675,31 +1718,65 @@ impl Statement for ReturnStatement {
 #[derive(Debug)]
 struct AddrOf {
     varname: String,
+    info: SourceInfo,
 }
 
 impl Expression for AddrOf {
-    fn codegen(&self, state: &mut CodeGenState) {
-        let addr = state.tracker.get(&self.varname);
-        state.adds(format!("mka {}", addr));
+    fn codegen(&self, state: &mut CodeGenState) -> Result<(), Diagnostic> {
+        if let Some(c) = state.get_const(&self.varname) {
+            return Err(Diagnostic::error(format!("Constant \"{}\" has no address", self.varname), self.info.clone())
+                .with_label(c.info.clone(), "constant declared here".to_string()));
+        }
+        let loc = state.get(&self.varname, &self.info)?;
+        state.load_addr(loc);
+        return Ok(());
     }
 }
 
 
-#[derive(Debug)]
-struct NOPStatement {}
-impl Statement for NOPStatement { fn codegen(&self, _state: &mut CodeGenState) {} }
-
-
 #[derive(Debug)]
 pub struct AST {
     nodes: BlockStatement,
 }
 
 impl AST {
-    pub fn codegen(&self) -> String {
-        let mut state = CodeGenState::new();
-        self.nodes.codegen(&mut state);
-        return state.code;
+    pub fn codegen(&self, stack_frames: bool) -> Result<String, Vec<Diagnostic>> {
+        let (state, mut errors) = self.generate(stack_frames);
+        errors.extend(state.calls.check());
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        // Der Stack beginnt hinter dem gesamten statisch belegten Speicher
+        if let Some(s) = state.stack {
+            return Ok(format!("mka {}\nsta {}\n{}", state.tracker.size, s.sp, state.code));
+        }
+        return Ok(state.code);
+    }
+
+    // Auch bei Fehlern, damit z.B. ein rekursiver Aufruf im Graph zu sehen ist
+    pub fn call_graph(&self, stack_frames: bool) -> CallGraph {
+        return self.generate(stack_frames).0.calls;
+    }
+
+    fn generate(&self, stack_frames: bool) -> (CodeGenState, Vec<Diagnostic>) {
+        let mut state = CodeGenState::new(stack_frames);
+        let mut errors: Vec<Diagnostic> = Vec::new();
+
+        state.inc_scope();
+        for s in &self.nodes.statements {
+            if let Err(e) = s.declare(&mut state) {
+                errors.push(e);
+            }
+        }
+        for s in &self.nodes.statements {
+            if let Err(e) = s.codegen(&mut state) {
+                errors.push(e);
+            }
+        }
+        state.dec_scope();
+        state.gen_runtime();
+        return (state, errors);
     }
 }
 
@@ -708,199 +1785,291 @@ impl AST {
 struct ParserState {
     tokenlist: Vec<Token>,
     i: usize,
+    errors: Vec<Diagnostic>,
+    max_errors: usize,
 }
 impl ParserState {
-    fn curr(&self) -> Token { return self.tokenlist[self.i].clone(); }
-    fn next(&self) -> Token { return self.tokenlist[self.i + 1].clone(); }
+    fn curr(&self) -> Token { return self.offset(0); }
+    fn next(&self) -> Token { return self.offset(1); }
     //fn prev(&self) -> Token { return self.tokenlist[self.i - 1].clone(); }
-    //fn offset(&self, offset: usize) -> Token { return self.tokenlist[self.i + offset].clone(); }
+    fn offset(&self, offset: usize) -> Token {
+        // Hinter dem Ende kommt immer nur EOF
+        let idx = usize::min(self.i + offset, self.tokenlist.len() - 1);
+        return self.tokenlist[idx].clone();
+    }
     fn advance_newlines(&mut self) {
         while self.curr().ttype == TokenType::Newline {
             self.i += 1;
         }
     }
-    fn expect_token(&self, ttype: TokenType, val: &str) {
+    fn expect_token(&self, ttype: TokenType, val: &str) -> Result<(), Diagnostic> {
         if !self.curr().equals(ttype, val) {
-            panic!("Expected \'{}\', but found \'{}\' at {}", val, self.curr().value, self.curr().position());
+            return Err(Diagnostic::error(format!("Expected \'{}\', but found {}", val, self.curr().describe()), self.curr().info));
         }
+        return Ok(());
     }
-    fn expect_token_type(&self, ttype: TokenType) {
+    fn expect_token_type(&self, ttype: TokenType) -> Result<(), Diagnostic> {
         if self.curr().ttype != ttype {
-            panic!("Expected {:?}, but found \'{}\' at {}", ttype, self.curr().value, self.curr().position());
+            return Err(Diagnostic::error(format!("Expected {:?}, but found {}", ttype, self.curr().describe()), self.curr().info));
+        }
+        return Ok(());
+    }
+
+    // Panic-Mode: Fehler merken und bis zum nächsten Zeilenende oder "}" überspringen
+    fn recover(&mut self, err: Diagnostic) {
+        // Nach dem Limit wird nichts mehr gesammelt, auch nicht die Folgefehler
+        // der umgebenden Blöcke, die beim Sprung ans Ende kein "}" mehr finden
+        if self.errors.len() < self.max_errors {
+            self.errors.push(err);
+        }
+        if self.errors.len() >= self.max_errors {
+            self.i = self.tokenlist.len() - 1;
+            return;
+        }
+
+        let mut depth: usize = 0;
+        loop {
+            let t = self.curr();
+            match t.ttype {
+                TokenType::EOF => return,
+                TokenType::Newline if depth == 0 => return,
+                TokenType::Parenthesis if t.value == "{" => depth += 1,
+                TokenType::Parenthesis if t.value == "}" => {
+                    if depth == 0 {
+                        return;
+                    }
+                    depth -= 1;
+                },
+                _ => {},
+            }
+            self.i += 1;
         }
     }
 }
 
-fn parse_atom(state: &mut ParserState) -> Box<dyn Expression> {
+fn parse_atom(state: &mut ParserState) -> Result<Box<dyn Expression>, Diagnostic> {
     // Number
     if state.curr().ttype == TokenType::Number {
         let n = state.curr().value.parse::<isize>().unwrap();
         state.i += 1;
-        return Box::new(Number{num: n});
+        return Ok(Box::new(Number{num: n}));
     }
 
     if state.curr().ttype == TokenType::Identifier {
+        let info = state.curr().info;
+
         // Array
         if state.next().equals(TokenType::Parenthesis, "[") {
             let aname = state.curr().value;
             state.i += 2;
-            let index = parse_expression(state);
-            state.expect_token(TokenType::Parenthesis, "]");
+            let index = parse_expression(state)?;
+            state.expect_token(TokenType::Parenthesis, "]")?;
             state.i += 1;
 
-            return Box::new(Array{arrname: aname, index: index});
+            return Ok(Box::new(Array{arrname: aname, index: index, info: info}));
         }
 
         // FunctionCall
         else if state.next().equals(TokenType::Parenthesis, "(") {
-            let mut params: Vec<Box<dyn Expression>> = Vec::new();
-
-            let fname = state.curr().value;
-            state.i += 2;
-
-            while !state.curr().equals(TokenType::Parenthesis, ")") {
-                params.push(parse_expression(state));
-                
-                if state.curr().ttype == TokenType::Comma {
-                    state.i += 1;
-                }
-            }
-            state.i += 1;
-
-            return Box::new(FunctionCall{ name: fname, params: params });
+            return Ok(Box::new(parse_functioncall(state)?));
         }
 
         // Variable
         else {
             let vname = state.curr().value;
             state.i += 1;
-            return Box::new(Variable{varname: vname});
+            return Ok(Box::new(Variable{varname: vname, info: info}));
         }
     }
 
     // Address of
     if state.curr().equals(TokenType::Keyword, "addr") {
         state.i += 1;
-        state.expect_token_type(TokenType::Identifier);
+        state.expect_token_type(TokenType::Identifier)?;
         let varname = state.curr().value.clone();
+        let info = state.curr().info;
         state.i += 1;
-        return Box::new(AddrOf{varname: varname});
+        return Ok(Box::new(AddrOf{varname: varname, info: info}));
     }
 
     if state.curr().equals(TokenType::Keyword, "deref") {
         state.i += 1;
-        let val = parse_atom(state);
-        return Box::new(UnaryOperator{operator: UnaryOperatorType::Deref, val: val});
+        let val = parse_atom(state)?;
+        return Ok(Box::new(UnaryOperator{operator: UnaryOperatorType::Deref, val: val}));
     }
 
     // (Expresssion)
-    state.expect_token(TokenType::Parenthesis, "(");
+    if !state.curr().equals(TokenType::Parenthesis, "(") {
+        return Err(Diagnostic::error(format!("Expected expression, but found {}", state.curr().describe()), state.curr().info));
+    }
     state.i += 1;
-    let a = parse_expression(state);
-    state.expect_token(TokenType::Parenthesis, "(");
+    let a = parse_expression(state)?;
+    state.expect_token(TokenType::Parenthesis, ")")?;
     state.i += 1;
-    return a;
+    return Ok(a);
 }
 
-fn parse_negation(state: &mut ParserState) -> Box<dyn Expression> {
-    if state.curr().equals(TokenType::Operator, "-") {
-        state.i += 1;
-        let a = parse_atom(state);
-        return Box::new(UnaryOperator{operator: UnaryOperatorType::Negation, val: a});
-    }
-    return parse_atom(state);
-}
+fn parse_functioncall(state: &mut ParserState) -> Result<FunctionCall, Diagnostic> {
+    let mut params: Vec<Box<dyn Expression>> = Vec::new();
 
-fn parse_multiplication(state: &mut ParserState) -> Box<dyn Expression> {
-    let a = parse_negation(state);
+    state.expect_token_type(TokenType::Identifier)?;
+    let fname = state.curr().value;
+    let info = state.curr().info;
+    state.i += 1;
+    state.expect_token(TokenType::Parenthesis, "(")?;
+    state.i += 1;
 
-    if state.curr().equals(TokenType::Operator, "*") {
-        state.i += 1;
-        let b = parse_multiplication(state);
-        return Box::new(BinaryOperator{operator: BinaryOperatorType::Times, lhs: a, rhs: b});
+    while !state.curr().equals(TokenType::Parenthesis, ")") {
+        params.push(parse_expression(state)?);
+        
+        if state.curr().ttype == TokenType::Comma {
+            state.i += 1;
+        }
     }
+    state.i += 1;
 
-    return a;
+    return Ok(FunctionCall{ name: fname, params: params, info: info });
 }
 
-fn parse_addition(state: &mut ParserState) -> Box<dyn Expression> {
-    let a = parse_multiplication(state);
-
-    if state.curr().equals(TokenType::Operator, "+") {
+fn parse_negation(state: &mut ParserState) -> Result<Box<dyn Expression>, Diagnostic> {
+    if state.curr().equals(TokenType::Operator, "-") {
         state.i += 1;
-        let b = parse_addition(state);
-        return Box::new(BinaryOperator{operator: BinaryOperatorType::Plus, lhs: a, rhs: b});
+        let a = parse_negation(state)?;
+        return Ok(Box::new(UnaryOperator{operator: UnaryOperatorType::Negation, val: a}));
     }
-    if state.curr().equals(TokenType::Operator, "-") {
+    if state.curr().equals(TokenType::Operator, "!") {
         state.i += 1;
-        let b = parse_addition(state);
-        return Box::new(BinaryOperator{operator: BinaryOperatorType::Minus, lhs: a, rhs: b});
+        let a = parse_negation(state)?;
+        return Ok(Box::new(UnaryOperator{operator: UnaryOperatorType::Not, val: a}));
     }
+    return parse_atom(state);
+}
 
-    return a;
+// Binäre Operatoren mit ihrer Bindungsstärke (höher bindet stärker).
+// Alle Operatoren sind linksassoziativ.
+const BINARY_OPERATORS: &[(&str, BinaryOperatorType, u8)] = &[
+    ("||", BinaryOperatorType::Or, 1),
+    ("&&", BinaryOperatorType::And, 2),
+    ("==", BinaryOperatorType::Equals, 3),
+    ("!=", BinaryOperatorType::NotEquals, 3),
+    ("<", BinaryOperatorType::LessThan, 3),
+    (">", BinaryOperatorType::GreaterThan, 3),
+    ("<=", BinaryOperatorType::LessEquals, 3),
+    (">=", BinaryOperatorType::GreaterEquals, 3),
+    ("+", BinaryOperatorType::Plus, 4),
+    ("-", BinaryOperatorType::Minus, 4),
+    ("*", BinaryOperatorType::Times, 5),
+    ("/", BinaryOperatorType::Divide, 5),
+    ("%", BinaryOperatorType::Modulo, 5),
+];
+
+fn binary_operator(t: &Token) -> Option<(BinaryOperatorType, u8)> {
+    if t.ttype != TokenType::Operator {
+        return None;
+    }
+    return BINARY_OPERATORS.iter().find(|(v, _, _)| *v == t.value).map(|(_, op, prec)| (*op, *prec));
 }
 
-fn parse_comparision(state: &mut ParserState) -> Box<dyn Expression> {
-    let a = parse_addition(state);
-
-    if state.curr().ttype == TokenType::Operator && vec!["==", "!=", "<", ">"].contains(&state.curr().value.as_str()) {
-        let op = match state.curr().value.as_str() {
-            "==" => BinaryOperatorType::Equals,
-            "!=" => BinaryOperatorType::NotEquals,
-            ">" => BinaryOperatorType::GreaterThan,
-            "<" => BinaryOperatorType::LessThan,
-            _ => BinaryOperatorType::Equals,
-        };
+// Precedence Climbing: rechts wird nur weitergelesen, solange die Operatoren stärker binden
+fn parse_binary(state: &mut ParserState, min_prec: u8) -> Result<Box<dyn Expression>, Diagnostic> {
+    let mut lhs = parse_negation(state)?;
 
+    while let Some((op, prec)) = binary_operator(&state.curr()) {
+        if prec < min_prec {
+            break;
+        }
         state.i += 1;
-        let b = parse_addition(state);
-        return Box::new(BinaryOperator{operator: op, lhs: a, rhs: b});
+        let rhs = parse_binary(state, prec + 1)?;
+        lhs = Box::new(BinaryOperator{operator: op, lhs: lhs, rhs: rhs});
     }
 
-    return a;
+    return Ok(lhs);
 }
 
-fn parse_expression(state: &mut ParserState) -> Box<dyn Expression> {
-    return parse_comparision(state);
+fn parse_expression(state: &mut ParserState) -> Result<Box<dyn Expression>, Diagnostic> {
+    return parse_binary(state, 0);
 }
 
-fn parse_statement(state: &mut ParserState) -> Box<dyn Statement> {
-    // EOF funkioniert, könnte sich aber ändern
-    if state.next().ttype == TokenType::EOF {
-        return Box::new(NOPStatement{});
-    }
-
+fn parse_statement(state: &mut ParserState) -> Result<Box<dyn Statement>, Diagnostic> {
     // BlockStatement
     if state.curr().equals(TokenType::Parenthesis, "{") {
-        return Box::new(parse_blockstatement(state));
+        return Ok(Box::new(parse_blockstatement(state)?));
     }
 
-    // VarDeclaration
+    // VarDeclaration, mehrere durch Kommas getrennt und jeweils mit optionalem Startwert
     if state.curr().ttype == TokenType::Keyword && state.curr().value == "var" {
+        let mut statements: Vec<Box<dyn Statement>> = Vec::new();
+        loop {
+            state.i += 1;
+            state.expect_token_type(TokenType::Identifier)?;
+            let varname = state.curr().value;
+            let info = state.curr().info;
+            state.i += 1;
+            statements.push(Box::new(VarDeclaration{varname: varname.clone(), value: None, info: info.clone()}));
+
+            if state.curr().ttype == TokenType::Equals {
+                state.i += 1;
+                let value = parse_expression(state)?;
+                statements.push(Box::new(VarAssignment{varname: varname, value: value, info: info}));
+            }
+
+            if state.curr().ttype != TokenType::Comma {
+                break;
+            }
+        }
+
+        if statements.len() == 1 {
+            return Ok(statements.pop().unwrap());
+        }
+        return Ok(Box::new(StatementList{statements: statements}));
+    }
+
+    // ConstDeclaration
+    if state.curr().equals(TokenType::Keyword, "const") {
         state.i += 1;
-        state.expect_token_type(TokenType::Identifier);
-        let varname = state.curr().value;
+        state.expect_token_type(TokenType::Identifier)?;
+        let name = state.curr().value;
+        let info = state.curr().info;
         state.i += 1;
-        return Box::new(VarDeclaration{varname: varname});
+        state.expect_token_type(TokenType::Equals)?;
+        state.i += 1;
+        let value = parse_expression(state)?;
+        return Ok(Box::new(ConstDeclaration{name: name, value: value, info: info}));
     }
 
     // VarAssignment
     if state.curr().ttype == TokenType::Identifier && state.next().ttype == TokenType::Equals {
         let varname = state.curr().value;
+        let info = state.curr().info;
         state.i += 2;
-        let value = parse_expression(state);
-        return Box::new(VarAssignment{varname: varname, value: value});
+        let value = parse_expression(state)?;
+        return Ok(Box::new(VarAssignment{varname: varname, value: value, info: info}));
+    }
+
+    // CallStatement, "call" ist optional
+    if state.curr().equals(TokenType::Keyword, "call") {
+        state.i += 1;
+        return Ok(Box::new(CallStatement{call: parse_functioncall(state)?}));
+    }
+    if state.curr().ttype == TokenType::Identifier && state.next().equals(TokenType::Parenthesis, "(") {
+        return Ok(Box::new(CallStatement{call: parse_functioncall(state)?}));
     }
 
-    // FuncDeclaration
+    // FuncDeclaration, "recursive func" legt Parameter und Variablen in einen Stack-Frame
+    let recursive = state.curr().equals(TokenType::Keyword, "recursive");
+    if recursive {
+        state.i += 1;
+        state.expect_token(TokenType::Keyword, "func")?;
+    }
     if state.curr().equals(TokenType::Keyword, "func") {
         let mut parm_names: Vec<String> = Vec::new();
 
         state.i += 1;
-        state.expect_token_type(TokenType::Identifier);
+        state.expect_token_type(TokenType::Identifier)?;
         let fname = state.curr().value;
+        let info = state.curr().info;
         state.i += 1;
-        state.expect_token(TokenType::Parenthesis, "(");
+        state.expect_token(TokenType::Parenthesis, "(")?;
 
         state.i += 1;
         while state.curr().ttype == TokenType::Identifier {
@@ -911,107 +2080,212 @@ fn parse_statement(state: &mut ParserState) -> Box<dyn Statement> {
                 state.i += 1;
             }
         }
+        state.expect_token(TokenType::Parenthesis, ")")?;
         state.i += 1;
 
-        let bs = parse_blockstatement(state);
-        return Box::new(FunctionDeclaration{name: fname, body: bs, param_names: parm_names});
+        let bs = parse_blockstatement(state)?;
+        return Ok(Box::new(FunctionDeclaration{name: fname, body: bs, param_names: parm_names, recursive: recursive, info: info}));
     }
 
     // IfStatement
     if state.curr().equals(TokenType::Keyword, "if") {
-        state.i += 1;
-        let condition = parse_expression(state);
-        let bs = parse_blockstatement(state);
-        return Box::new(IfStatement{condition: condition, block: bs});
+        return Ok(Box::new(parse_ifstatement(state)?));
     }
 
     // WhileLoop
     if state.curr().equals(TokenType::Keyword, "while") {
         state.i += 1;
-        let condition = parse_expression(state);
-        let bs = parse_blockstatement(state);
-        return Box::new(WhileLoop{condition: condition, block: bs});
+        let condition = parse_expression(state)?;
+        let bs = parse_blockstatement(state)?;
+        return Ok(Box::new(WhileLoop{condition: condition, block: bs, step: None}));
     }
 
-    // ArrDeclaration
-    if state.curr().equals(TokenType::Keyword, "arr") {
+    // ForLoop, wird zu einem Block aus VarDeclaration, VarAssignment und WhileLoop
+    if state.curr().equals(TokenType::Keyword, "for") {
         state.i += 1;
-        state.expect_token_type(TokenType::Identifier);
-        let aname = state.curr().value;
+        state.expect_token_type(TokenType::Identifier)?;
+        let varname = state.curr().value;
+        let info = state.curr().info;
         state.i += 1;
-        state.expect_token(TokenType::Parenthesis, "[");
+        state.expect_token_type(TokenType::Equals)?;
         state.i += 1;
-        let alen = state.curr().value.parse::<usize>().unwrap();
+        let start = parse_expression(state)?;
+        state.expect_token(TokenType::Keyword, "to")?;
         state.i += 1;
-        state.expect_token(TokenType::Parenthesis, "]");
+        let end = parse_expression(state)?;
+        let bs = parse_blockstatement(state)?;
+
+        let var = || Box::new(Variable{varname: varname.clone(), info: info.clone()});
+        let condition = Box::new(BinaryOperator{operator: BinaryOperatorType::LessThan, lhs: var(), rhs: end});
+        let increment = Box::new(BinaryOperator{operator: BinaryOperatorType::Plus, lhs: var(), rhs: Box::new(Number{num: 1})});
+        let step = Box::new(VarAssignment{varname: varname.clone(), value: increment, info: info.clone()});
+
+        // Eigener Block, damit die Zählvariable nach der Schleife wieder freigegeben wird
+        return Ok(Box::new(BlockStatement{statements: vec![
+            Box::new(VarDeclaration{varname: varname.clone(), value: Some(start), info: info.clone()}),
+            Box::new(WhileLoop{condition: condition, block: bs, step: Some(step)}),
+        ]}));
+    }
+
+    // BreakStatement
+    if state.curr().equals(TokenType::Keyword, "break") {
+        let info = state.curr().info;
         state.i += 1;
-        state.advance_newlines();
-        return Box::new(ArrDeclaration{arrname: aname, arrlen: alen});
+        return Ok(Box::new(BreakStatement{info: info}));
+    }
+
+    // ContinueStatement
+    if state.curr().equals(TokenType::Keyword, "continue") {
+        let info = state.curr().info;
+        state.i += 1;
+        return Ok(Box::new(ContinueStatement{info: info}));
+    }
+
+    // ArrDeclaration
+    if state.curr().equals(TokenType::Keyword, "arr") {
+        state.i += 1;
+        state.expect_token_type(TokenType::Identifier)?;
+        let aname = state.curr().value;
+        let info = state.curr().info;
+        state.i += 1;
+
+        let mut alen: Option<Box<dyn Expression>> = None;
+        if state.curr().equals(TokenType::Parenthesis, "[") {
+            state.i += 1;
+            alen = Some(parse_expression(state)?);
+            state.expect_token(TokenType::Parenthesis, "]")?;
+            state.i += 1;
+        }
+
+        // Startwerte: {1, 2, 3} oder "Text"
+        let mut values: Vec<Box<dyn Expression>> = Vec::new();
+        if state.curr().ttype == TokenType::Equals {
+            state.i += 1;
+            if state.curr().ttype == TokenType::String {
+                // Mit 0 am Ende, damit das Ende des Texts erkennbar ist
+                for c in state.curr().value.chars().chain(['\0']) {
+                    values.push(Box::new(Number{num: c as isize}));
+                }
+                state.i += 1;
+            } else {
+                state.expect_token(TokenType::Parenthesis, "{")?;
+                state.i += 1;
+                state.advance_newlines();
+                while !state.curr().equals(TokenType::Parenthesis, "}") {
+                    values.push(parse_expression(state)?);
+                    state.advance_newlines();
+                    if state.curr().ttype != TokenType::Comma {
+                        break;
+                    }
+                    state.i += 1;
+                    state.advance_newlines();
+                }
+                state.expect_token(TokenType::Parenthesis, "}")?;
+                state.i += 1;
+            }
+        } else if alen.is_none() {
+            return Err(Diagnostic::error(format!("Array \"{}\" needs a length or initial values", aname), info));
+        }
+
+        return Ok(Box::new(ArrDeclaration{arrname: aname, arrlen: alen, values: values, info: info}));
     }
 
     // ArrAssignment
     if state.curr().ttype == TokenType::Identifier && state.next().equals(TokenType::Parenthesis, "[") {
         let aname = state.curr().value;
+        let info = state.curr().info;
         state.i += 2;
-        let index = parse_expression(state);
-        state.expect_token(TokenType::Parenthesis, "]");
+        let index = parse_expression(state)?;
+        state.expect_token(TokenType::Parenthesis, "]")?;
         state.i += 1;
-        state.expect_token_type(TokenType::Equals);
+        state.expect_token_type(TokenType::Equals)?;
         state.i += 1;
-        let value = parse_expression(state);
+        let value = parse_expression(state)?;
         state.advance_newlines();
-        return Box::new(ArrAssignment{arrname: aname, index: index, value: value});
+        return Ok(Box::new(ArrAssignment{arrname: aname, index: index, value: value, info: info}));
     }
 
     // ReturnStatement
     if state.curr().equals(TokenType::Keyword, "return") {
         state.i += 1;
-        let val = parse_expression(state);
+        let val = parse_expression(state)?;
 
-        return Box::new(ReturnStatement{value: val});
+        return Ok(Box::new(ReturnStatement{value: val}));
     }
 
     // DerefAssignment
     if state.curr().equals(TokenType::Keyword, "deref") {
         state.i += 1;
-        let addr = parse_expression(state);
+        let addr = parse_expression(state)?;
 
-        state.expect_token_type(TokenType::Equals);
+        state.expect_token_type(TokenType::Equals)?;
         state.i += 1;
-        let value = parse_expression(state);
+        let value = parse_expression(state)?;
+
+        return Ok(Box::new(DerefAssignment{addr: addr, value: value}));
+    }
+
+    return Err(Diagnostic::error(format!("Syntax error: unexpected {}", state.curr().describe()), state.curr().info));
+}
 
-        return Box::new(DerefAssignment{addr: addr, value: value});
+fn parse_ifstatement(state: &mut ParserState) -> Result<IfStatement, Diagnostic> {
+    state.expect_token(TokenType::Keyword, "if")?;
+    state.i += 1;
+    let condition = parse_expression(state)?;
+    let bs = parse_blockstatement(state)?;
+
+    let mut else_block: Option<Box<dyn Statement>> = None;
+    if state.curr().equals(TokenType::Keyword, "else") {
+        state.i += 1;
+        if state.curr().equals(TokenType::Keyword, "if") {
+            else_block = Some(Box::new(parse_ifstatement(state)?));
+        } else {
+            else_block = Some(Box::new(parse_blockstatement(state)?));
+        }
     }
 
-    panic!("Syntax error at {}!", state.curr().position());
-    //return Box::new(NOPStatement{});
+    return Ok(IfStatement{condition: condition, block: bs, else_block: else_block});
 }
 
-fn parse_blockstatement(state: &mut ParserState) -> BlockStatement {
-    state.expect_token(TokenType::Parenthesis, "{");
+fn parse_blockstatement(state: &mut ParserState) -> Result<BlockStatement, Diagnostic> {
+    state.expect_token(TokenType::Parenthesis, "{")?;
     state.i += 1;
     state.advance_newlines();
 
     let mut n_bs = BlockStatement {statements: Vec::new()};
-    n_bs.statements.push(parse_statement(state));
-    state.advance_newlines();
 
     while !state.curr().equals(TokenType::Parenthesis, "}") {
-        n_bs.statements.push(parse_statement(state));
+        if state.curr().ttype == TokenType::EOF {
+            return Err(Diagnostic::error("Unexpected end of file, expected \'}\'".to_string(), state.curr().info));
+        }
+        match parse_statement(state) {
+            Ok(s) => n_bs.statements.push(s),
+            Err(e) => state.recover(e),
+        }
         state.advance_newlines();
     }
 
     state.i += 1;
     state.advance_newlines();
 
-    return n_bs;
+    return Ok(n_bs);
 }
 
 fn parse_program(state: &mut ParserState) -> AST {
     let mut nodes = Vec::new();
 
+    state.advance_newlines();
     while state.curr().ttype != TokenType::EOF {
-        nodes.push(parse_statement(state));
+        match parse_statement(state) {
+            Ok(s) => nodes.push(s),
+            Err(e) => {
+                state.recover(e);
+                // Auf oberster Ebene gehört ein "}" zu keinem Block und wird übersprungen
+                if state.curr().equals(TokenType::Parenthesis, "}") {
+                    state.i += 1;
+                }
+            },
+        }
         state.advance_newlines();
     }
 
@@ -1019,11 +2293,17 @@ fn parse_program(state: &mut ParserState) -> AST {
 }
 
 
-pub fn parse(tokenlist: Vec<Token>) -> AST {
+pub fn parse(tokenlist: Vec<Token>, max_errors: usize) -> Result<AST, Vec<Diagnostic>> {
     let mut state = ParserState {
         tokenlist: tokenlist,
         i: 0,
+        errors: Vec::new(),
+        max_errors: usize::max(max_errors, 1),
     };
 
-    return parse_program(&mut state);
-}
\ No newline at end of file
+    let ast = parse_program(&mut state);
+    if !state.errors.is_empty() {
+        return Err(state.errors);
+    }
+    return Ok(ast);
+}
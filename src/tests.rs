@@ -0,0 +1,1051 @@
+use std::collections::HashMap;
+
+use crate::diagnostic::Diagnostic;
+use crate::lexer::{lexer, Token, TokenType};
+use crate::parser;
+
+fn compile(src: &str) -> String {
+    return compile_with(src, false);
+}
+
+fn compile_with(src: &str, stack_frames: bool) -> String {
+    let res = lexer(src.to_string(), "test".to_string())
+        .and_then(|tokens| parser::parse(tokens, 20))
+        .and_then(|ast| ast.codegen(stack_frames));
+    match res {
+        Ok(code) => code,
+        Err(diagnostics) => {
+            let msgs: Vec<String> = diagnostics.iter().map(|d| d.render(src, false)).collect();
+            panic!("compilation failed:\n{}", msgs.join("\n"));
+        },
+    }
+}
+
+// Für Programme mit Fehlern: alle Diagnostics aus Lexer, Parser oder Codegenerierung
+fn compile_errors(src: &str) -> Vec<Diagnostic> {
+    let res = lexer(src.to_string(), "test".to_string())
+        .and_then(|tokens| parser::parse(tokens, 20))
+        .and_then(|ast| ast.codegen(false));
+    match res {
+        Ok(_) => panic!("compilation succeeded, expected errors"),
+        Err(diagnostics) => diagnostics,
+    }
+}
+
+fn messages(errors: &[Diagnostic]) -> Vec<&str> {
+    return errors.iter().map(|e| e.message.as_str()).collect();
+}
+
+fn tokens(src: &str) -> Vec<Token> {
+    return lexer(src.to_string(), "test".to_string()).unwrap();
+}
+
+fn lex_errors(src: &str) -> Vec<Diagnostic> {
+    return lexer(src.to_string(), "test".to_string()).unwrap_err();
+}
+
+// Kleiner Emulator der Karol-CPU, damit die Tests die Ergebnisse im Speicher prüfen können
+fn run(code: &str) -> Vec<isize> {
+    return run_counted(code).0;
+}
+
+// Zusätzlich die Anzahl der ausgeführten Befehle
+fn run_counted(code: &str) -> (Vec<isize>, usize) {
+    let program: Vec<Vec<&str>> = code.lines().map(|l| l.split_whitespace().collect::<Vec<&str>>()).filter(|l| !l.is_empty()).collect();
+    let mut labels: HashMap<&str, usize> = HashMap::new();
+    for (i, ins) in program.iter().enumerate() {
+        if ins[0] == "label" {
+            labels.insert(ins[1], i);
+        }
+    }
+
+    let mut mem: Vec<isize> = vec![0; 1000];
+    let mut stack: Vec<usize> = Vec::new();
+    let mut acc: isize = 0;
+    let mut pc: usize = 0;
+    let mut steps: usize = 0;
+
+    while pc < program.len() {
+        steps += 1;
+        assert!(steps < 1_000_000, "program did not terminate");
+
+        let ins = &program[pc];
+        let num = || ins[1].parse::<isize>().unwrap();
+        let addr = || ins[1].parse::<usize>().unwrap();
+        let target = || labels[ins[1]];
+        pc += 1;
+
+        match ins[0] {
+            "label" => {},
+            "mka" => acc = num(),
+            "lda" => acc = mem[addr()],
+            "sta" => mem[addr()] = acc,
+            "ldad" => acc = mem[mem[addr()] as usize],
+            "stad" => { let a = mem[addr()] as usize; mem[a] = acc; },
+            "add" => acc += mem[addr()],
+            "neg" => acc = -acc,
+            "jmp" => pc = target(),
+            "jz" => if acc == 0 { pc = target() },
+            "jp" => if acc > 0 { pc = target() },
+            "jn" => if acc < 0 { pc = target() },
+            "call" => { stack.push(pc); pc = target(); },
+            "ret" => pc = stack.pop().expect("ret without call"),
+            other => panic!("unknown instruction: {}", other),
+        }
+    }
+    return (mem, steps);
+}
+
+fn eval(src: &str) -> Vec<isize> {
+    return run(&compile(src));
+}
+
+// Werte der genannten Variablen am Programmende, unabhängig davon, wo der Compiler sie ablegt.
+// Sie werden in ein Array kopiert, dessen Adresse zum Schluss in Zelle 0 steht.
+fn eval_vars(src: &str, names: &[&str]) -> Vec<isize> {
+    return eval_vars_with(src, false, names);
+}
+
+fn eval_vars_with(src: &str, stack_frames: bool, names: &[&str]) -> Vec<isize> {
+    let src = format!("{}\narr ergebnisse = {{{}}}\nderef 0 = addr ergebnisse\n", src, names.join(", "));
+    let mem = run(&compile_with(&src, stack_frames));
+    let start = mem[0] as usize;
+    assert!(start > 0, "result array overlaps cell 0");
+    return mem[start..start + names.len()].to_vec();
+}
+
+// ===== Lexer =====
+
+#[test]
+fn token_positions() {
+    // (Zeile, Spalte, Endspalte, Byte-Offset)
+    let pos = |t: &Token| (t.info.line, t.info.column, t.info.end_column, t.info.offset);
+    let toks = tokens("var äb = 12\n\tx<=ä + 345\r\ny");
+    let found: Vec<_> = toks.iter().map(pos).collect();
+    assert_eq!(found, [
+        (1, 1, 4, 0), (1, 5, 7, 4), (1, 8, 9, 8), (1, 10, 12, 10), (1, 12, 13, 12),
+        (2, 2, 3, 14), (2, 3, 5, 15), (2, 5, 6, 17), (2, 7, 8, 20), (2, 9, 12, 22), (2, 13, 14, 26),
+        (3, 1, 2, 27), (3, 2, 3, 28),
+    ]);
+    assert!(toks.iter().all(|t| t.info.file == "test"));
+    assert_eq!(toks.last().unwrap().ttype, TokenType::EOF);
+}
+
+#[test]
+fn invalid_characters() {
+    let errors = lex_errors("a = 5 ; b\n$x @\n");
+    assert_eq!(messages(&errors), ["Invalid character \';\'", "Invalid character \'$\'", "Invalid character \'@\'"]);
+    let pos: Vec<_> = errors.iter().map(|e| (e.span.line, e.span.column)).collect();
+    assert_eq!(pos, [(1, 7), (2, 1), (2, 4)]);
+
+    // Leerzeichen, Tabs und '\r' sind keine Fehler
+    let types: Vec<TokenType> = tokens(" a\t=\r\n5").iter().map(|t| t.ttype).collect();
+    assert_eq!(types, [TokenType::Identifier, TokenType::Equals, TokenType::Newline, TokenType::Number, TokenType::EOF]);
+}
+
+#[test]
+fn comments() {
+    let src = "// am Dateianfang\nvar a // hinter einer Anweisung\n/* über\n/* verschachtelt */ mehrere\n Zeilen */ a = 1 /**/\n";
+    let toks = tokens(src);
+    let found: Vec<(TokenType, usize)> = toks.iter().map(|t| (t.ttype, t.info.line)).collect();
+    assert_eq!(found, [
+        (TokenType::Newline, 1),
+        (TokenType::Keyword, 2), (TokenType::Identifier, 2), (TokenType::Newline, 2),
+        (TokenType::Identifier, 5), (TokenType::Equals, 5), (TokenType::Number, 5), (TokenType::Newline, 5),
+        (TokenType::EOF, 6),
+    ]);
+    assert_eq!(toks[4].info.column, 12);
+
+    let errors = lex_errors("a\n  /* /* */\n");
+    assert_eq!(messages(&errors), ["Unterminated block comment"]);
+    assert_eq!((errors[0].span.line, errors[0].span.column), (2, 3));
+    // Ein Kommentar bis zum Dateiende ohne Zeilenumbruch
+    assert_eq!(tokens("a // b").len(), 2);
+}
+
+#[test]
+fn identifiers() {
+    let toks = tokens("x1 tmp_2 _a addiereFünf Ähre var variable");
+    let found: Vec<(TokenType, &str)> = toks.iter().map(|t| (t.ttype, t.value.as_str())).collect();
+    assert_eq!(found, [
+        (TokenType::Identifier, "x1"), (TokenType::Identifier, "tmp_2"), (TokenType::Identifier, "_a"),
+        (TokenType::Identifier, "addiereFünf"), (TokenType::Identifier, "Ähre"),
+        (TokenType::Keyword, "var"), (TokenType::Identifier, "variable"), (TokenType::EOF, ""),
+    ]);
+    // Bezeichner dürfen nicht mit einer Ziffer beginnen
+    assert_eq!(messages(&lex_errors("2b")), ["Invalid digit \'b\' in number literal"]);
+
+    let mem = eval("
+        var zähler_1 = 4
+        var _größe = zähler_1 + 1
+    ");
+    assert_eq!(&mem[..2], &[4, 5]);
+}
+
+#[test]
+fn number_and_character_literals() {
+    let values: Vec<String> = tokens("0x1F 0Xff 0b101 0B1 1_000 999_999 007 'A' '\\n' '\\'' 'ü'")
+        .iter().filter(|t| t.ttype == TokenType::Number).map(|t| t.value.clone()).collect();
+    assert_eq!(values, ["31", "255", "5", "1", "1000", "999999", "7", "65", "10", "39", "252"]);
+
+    let errors = lex_errors("1_000_000\n0xF_FFFF\n1_\n0x\n0b2\n'\\q'\n''\n'a\n'\u{10FFFF}'\n");
+    assert_eq!(messages(&errors), [
+        "Number literal out of range",
+        "Number literal out of range",
+        "Number literal must not end with \'_\'",
+        "Number literal has no digits",
+        "Invalid digit \'2\' in number literal",
+        "Unknown escape sequence \'\\q\'",
+        "Empty character literal",
+        "Unterminated character literal",
+        "Character literal out of range",
+    ]);
+    assert_eq!(errors[0].notes, ["numbers must be between -999999 and 999999"]);
+    let lines: Vec<usize> = errors.iter().map(|e| e.span.line).collect();
+    assert_eq!(lines, [1, 2, 3, 4, 5, 6, 7, 8, 9]);
+
+    // Die Grenze selbst ist erlaubt, auch negativ
+    let mem = eval("
+        var a = 999999
+        var b = -999_999
+        var c = 'a' + 0b1
+    ");
+    assert_eq!(&mem[..3], &[999999, -999999, 98]);
+}
+
+#[test]
+fn unterminated_literals_at_end_of_file() {
+    // Ohne abschließenden Zeilenumbruch
+    let errors = lex_errors("arr s = \"ab\\");
+    assert_eq!(messages(&errors), ["Unterminated string literal"]);
+    assert_eq!((errors[0].span.column, errors[0].span.end_column), (9, 13));
+
+    let errors = lex_errors("var c = '\\");
+    assert_eq!(messages(&errors), ["Unterminated character literal"]);
+
+    let errors = lex_errors("arr s = \"ab\\\nvar c = '\\\nvar d\n");
+    assert_eq!(messages(&errors), ["Unterminated string literal", "Unterminated character literal"]);
+    let lines: Vec<usize> = errors.iter().map(|e| e.span.line).collect();
+    assert_eq!(lines, [1, 2]);
+}
+
+// ===== Fehlermeldungen =====
+
+#[test]
+fn missing_operand() {
+    let errors = compile_errors("var c =\nwhile {\n}\nvar d = (1 +)\n");
+    assert_eq!(messages(&errors), [
+        "Expected expression, but found end of line",
+        "Expected expression, but found \'{\'",
+        "Expected expression, but found \')\'",
+    ]);
+    assert_eq!((errors[1].span.line, errors[1].span.column), (2, 7));
+}
+
+#[test]
+fn recovery_reports_every_line() {
+    let src = "var a = )\nvar b\nb = = 2\nif 1 {\n    x y\n    b = 1\n}\n}\nwhile 1 {\n    ( \n}\nb = 3 +\n";
+    let tokens = lexer(src.to_string(), "test".to_string()).unwrap();
+    let errors = parser::parse(tokens, 20).unwrap_err();
+    let lines: Vec<usize> = errors.iter().map(|e| e.span.line).collect();
+    assert_eq!(lines, [1, 3, 5, 8, 10, 12]);
+}
+
+#[test]
+fn recovery_keeps_closing_brace_of_block() {
+    let errors = compile_errors("var a\nif 1 { a = }\nwhile 1 {\n    a = (1 + }\na = )\n");
+    assert_eq!(messages(&errors), [
+        "Expected expression, but found \'}\'",
+        "Expected expression, but found \'}\'",
+        "Expected expression, but found \')\'",
+    ]);
+    let lines: Vec<usize> = errors.iter().map(|e| e.span.line).collect();
+    assert_eq!(lines, [2, 4, 5]);
+
+    // Ein überzähliges "}" auf oberster Ebene wird weiterhin übersprungen
+    let errors = compile_errors("var a\n}\na = )\n");
+    let lines: Vec<usize> = errors.iter().map(|e| e.span.line).collect();
+    assert_eq!(lines, [2, 3]);
+}
+
+#[test]
+fn max_errors_is_a_hard_limit() {
+    let src = "if 1 {\n a b\n c d\n e f\n";
+    let parse = |max| parser::parse(lexer(src.to_string(), "test".to_string()).unwrap(), max).unwrap_err();
+    assert_eq!(parse(20).len(), 4);
+    assert_eq!(messages(&parse(20))[3], "Unexpected end of file, expected \'}\'");
+
+    let errors = parse(2);
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[1].span.line, 3);
+    // 0 heißt wie 1: der erste Fehler bricht ab
+    assert_eq!(parse(0).len(), 1);
+}
+
+#[test]
+fn indexing_a_variable() {
+    let errors = compile_errors("var a\n{\n    a[1] = 2\n}\n");
+    assert_eq!(messages(&errors), ["\"a\" is not an array"]);
+    assert_eq!((errors[0].span.line, errors[0].span.column), (3, 5));
+    assert_eq!(errors[0].labels[0].message, "variable declared here");
+    assert_eq!((errors[0].labels[0].span.line, errors[0].labels[0].span.column), (1, 5));
+}
+
+#[test]
+fn render_snippet_with_label() {
+    let src = "var a\n\tb = a[0]\n";
+    let errors = compile_errors(src);
+    assert_eq!(errors[0].render(src, false), [
+        "error: \"a\" is not an array",
+        " --> test:2:6",
+        "  |",
+        "2 |     b = a[0]",
+        "  |         ^",
+        "  |",
+        "1 | var a",
+        "  |     - variable declared here",
+        "  |",
+        "  = note: only names declared with \"arr\" can be indexed",
+    ].join("\n"));
+    // Mit Farbe nur zusätzliche Escape-Sequenzen
+    assert!(errors[0].render(src, true).contains("\x1b[1;31merror\x1b[0m"));
+}
+
+#[test]
+fn inner_declarations_shadow_outer_ones() {
+    let mem = eval("
+        var a = 1
+        var b
+        {
+            var a = 2
+            b = a
+        }
+        a = a + 10
+    ");
+    assert_eq!(&mem[..2], &[11, 2]);
+}
+
+// ===== Beispiele aus docs/docs.tex =====
+
+// Jedes Beispiel in der Dokumentation muss kompilieren, "a" ist in manchen schon deklariert
+#[test]
+fn docs_all_listings_compile() {
+    let docs = include_str!("../docs/docs.tex");
+    let listings: Vec<&str> = docs.split("\\begin{lstlisting}").skip(1)
+        .map(|l| l.split("\\end{lstlisting}").next().unwrap())
+        .filter(|l| !l.contains("// Syntax"))
+        .collect();
+    assert!(listings.len() >= 10);
+    for l in listings {
+        compile(&format!("var a\n{}", l));
+    }
+}
+
+#[test]
+fn docs_variables_and_arrays() {
+    let mem = eval("
+        var a
+        var b
+
+        a = 5
+        b = 69420
+
+        arr b[10]
+        b[3] = 666
+    ");
+    assert_eq!(mem[0], 5);
+    assert_eq!(mem[1], 69420);
+}
+
+#[test]
+fn docs_array_initializers() {
+    let mem = eval("
+        arr primzahlen[5] = {2, 3, 5, 7, 11}
+        arr nullen[2 * 5] = {0}
+        arr text = \"Hallo\"
+    ");
+    assert_eq!(&mem[..5], &[2, 3, 5, 7, 11]);
+    assert_eq!(&mem[5..15], &[0; 10]);
+    assert_eq!(&mem[15..21], &[72, 97, 108, 108, 111, 0]);
+}
+
+#[test]
+fn docs_constants() {
+    let mem = eval("
+        const N = 10
+        arr werte[N * 2]
+        arr rest[1] = {7}
+    ");
+    // Die Konstante belegt keinen Speicher, "rest" liegt direkt hinter den 20 Zellen
+    assert_eq!(mem[20], 7);
+}
+
+#[test]
+fn docs_comments() {
+    let mem = eval("
+        var a // Kommentar hinter einer Anweisung
+        /* mehrzeiliger
+           /* verschachtelter */ Kommentar */
+        a = 3
+    ");
+    assert_eq!(mem[0], 3);
+}
+
+#[test]
+fn docs_expressions() {
+    let mem = eval("
+        var a
+        var b
+        a = (5 - 3) * 7
+        // = 14
+        b = a == 13 + 1
+        // = 1, bzw. \"wahr\"
+    ");
+    assert_eq!(mem[0], 14);
+    assert_eq!(mem[1], 1);
+}
+
+#[test]
+fn docs_if() {
+    // "a" ist im Beispiel schon deklariert
+    let mem = eval("
+        var a
+        a = 4
+        // Beispiel
+        if a == 4 {
+            a = 14
+        }
+    ");
+    assert_eq!(mem[0], 14);
+}
+
+#[test]
+fn docs_else_if() {
+    let mem = eval("
+        var a
+        a = 5
+        // Beispiel
+        if a == 4 {
+            a = 14
+        } else if a == 5 {
+            a = 15
+        } else {
+            a = 0
+        }
+    ");
+    assert_eq!(mem[0], 15);
+}
+
+#[test]
+fn docs_while() {
+    let mem = eval("
+        // Beispiel
+        var a
+        a = 0
+        while a < 10 {
+            a = a + 1
+        }
+    ");
+    assert_eq!(mem[0], 10);
+}
+
+#[test]
+fn docs_for() {
+    let mem = eval("
+        // Beispiel
+        var summe
+        summe = 0
+        for i = 0 to 10 {
+            summe = summe + i
+        }
+    ");
+    assert_eq!(mem[0], 45);
+}
+
+#[test]
+fn docs_function() {
+    let mem = eval_vars("
+        // Beispiel
+        func addiereFünf(a) {
+            var b = a + 5
+            return b
+        }
+        var ergebnis
+        ergebnis = addiereFünf(45)
+        // ergebnis ist 50
+    ", &["ergebnis"]);
+    assert_eq!(mem, [50]);
+}
+
+#[test]
+fn docs_recursive_function() {
+    let mem = eval_vars("
+        // Beispiel
+        recursive func fakultät(n) {
+            if n <= 1 {
+                return 1
+            }
+            return n * fakultät(n - 1)
+        }
+        var ergebnis
+        ergebnis = fakultät(5)
+        // ergebnis ist 120
+    ", &["ergebnis"]);
+    assert_eq!(mem, [120]);
+}
+
+// ===== Ausdrücke =====
+
+#[test]
+fn parenthesized_expressions() {
+    let mem = eval("
+        var a
+        a = 2 * (3 + 4) - (10 - (1 + 1))
+    ");
+    assert_eq!(mem[0], 6);
+}
+
+#[test]
+fn subtraction_is_left_associative() {
+    let mem = eval("
+        var a
+        a = 10 - 3 - 2
+    ");
+    assert_eq!(mem[0], 5);
+}
+
+#[test]
+fn mixed_precedence() {
+    let mem = eval("
+        var a = 20, b = 3, c = 4, d = 5
+        arr r[5]
+        r[0] = a - b * c - d
+        r[1] = a - b - c * d + 1
+        r[2] = 100 / 10 / 5
+        r[3] = a * b - c * d
+        r[4] = a - -b - c
+    ");
+    assert_eq!(&mem[4..9], &[3, -2, 2, 40, 19]);
+}
+
+#[test]
+fn multiplication() {
+    let mem = eval("
+        arr r[9]
+        r[0] = 6 * 7
+        r[1] = -6 * 7
+        r[2] = 6 * -7
+        r[3] = -6 * -7
+        r[4] = 0 * 5
+        r[5] = 5 * 0
+        r[6] = 0 * -5
+        r[7] = 999 * 1001
+        r[8] = 2 * 3 * 4 + 1
+    ");
+    assert_eq!(&mem[..9], &[42, -42, -42, 42, 0, 0, 0, 999999, 25]);
+}
+
+#[test]
+fn multiplication_is_shared_routine() {
+    let code = compile("
+        var a
+        a = 3 * 4 * 5 * 6
+    ");
+    assert_eq!(code.matches("label rt_mul").count(), 1);
+    assert_eq!(code.matches("call rt_mul").count(), 3);
+}
+
+#[test]
+fn multiplication_steps_are_logarithmic() {
+    let steps = |b: isize| {
+        let (mem, steps) = run_counted(&compile(&format!("var a = 7 * {}", b)));
+        assert_eq!(mem[0], 7 * b);
+        steps
+    };
+    // Jede Verdopplung von b kostet nur einen Durchlauf mehr, ein gesetztes Bit ein paar Befehle
+    let per_bit = steps(4) - steps(2);
+    assert!(per_bit < 40);
+    assert_eq!(steps(524288), steps(1) + 19 * per_bit);
+    assert!(steps(999999) < steps(1) + 19 * (per_bit + 4));
+    assert_eq!(steps(-999999) - steps(999999), steps(-1) - steps(1));
+}
+
+#[test]
+fn division_and_modulo() {
+    let mem = eval("
+        arr r[14]
+        r[0] = 17 / 5
+        r[1] = 17 % 5
+        r[2] = -17 / 5
+        r[3] = -17 % 5
+        r[4] = 17 / -5
+        r[5] = 17 % -5
+        r[6] = -17 / -5
+        r[7] = -17 % -5
+        r[8] = 3 / 7
+        r[9] = 999999 / 3
+        r[10] = 7 / 0
+        r[11] = 7 % 0
+        r[12] = 100 - 20 / 4 * 3
+        r[13] = 123456 % 1000
+    ");
+    assert_eq!(&mem[..14], &[3, 2, -3, -2, -3, 2, 3, -2, 0, 333333, 0, 7, 85, 456]);
+}
+
+#[test]
+fn comparisons() {
+    let mem = eval("
+        arr r[12]
+        r[0] = 3 <= 4
+        r[1] = 4 <= 4
+        r[2] = 5 <= 4
+        r[3] = 3 >= 4
+        r[4] = 4 >= 4
+        r[5] = 5 >= 4
+        r[6] = -2 < -1
+        r[7] = -1 > -2
+        r[8] = 1 == 1
+        r[9] = 1 != 1
+        r[10] = 2 < 2
+        r[11] = 2 > 2
+    ");
+    assert_eq!(&mem[..12], &[1, 1, 0, 0, 1, 1, 1, 1, 1, 0, 0, 0]);
+}
+
+#[test]
+fn logical_operators() {
+    let mem = eval("
+        var a
+        var b
+        var c
+        var d
+        var e
+        a = 1 == 1 && 2 == 2
+        b = 0 || 5
+        c = !7 || 0
+        d = !!(-3) && 1 == 2 || 1
+        e = !(1 == 2)
+    ");
+    assert_eq!(&mem[..5], &[1, 1, 0, 1, 1]);
+}
+
+#[test]
+fn logical_operators_short_circuit() {
+    // Jeder Aufruf von f setzt x, y hält den Wert vor dem letzten Ausdruck fest
+    let mem = eval_vars("
+        var x
+        func f() {
+            x = 99
+            return 1
+        }
+        var a
+        var b
+        x = 0
+        a = 0 && f()
+        b = 1 || f()
+        var y
+        var c
+        y = x
+        c = 1 && f()
+    ", &["a", "b", "y", "c", "x"]);
+    assert_eq!(mem, [0, 1, 0, 1, 99]);
+}
+
+// ===== Anweisungen =====
+
+#[test]
+fn if_else_chain() {
+    let mem = eval("
+        var a
+        var b
+        var c
+        var d
+        a = 2
+        if a == 1 {
+            b = 10
+        } else if a == 2 {
+            b = 20
+        } else {
+            b = 30
+        }
+        if a == 5 {
+            c = 1
+        } else {
+            c = 2
+        }
+        if a == 9 {
+            d = 1
+        } else if a == 8 {
+            d = 2
+        } else {
+            d = 3
+        }
+    ");
+    assert_eq!(mem[1], 20);
+    assert_eq!(mem[2], 2);
+    assert_eq!(mem[3], 3);
+}
+
+#[test]
+fn var_declaration_with_initializer() {
+    let mem = eval("
+        var a = 5
+        var b, c = a * 2, d
+        d = c + 1
+        {
+            var e = d
+            b = e
+        }
+        var f = 3
+    ");
+    assert_eq!(&mem[..5], &[5, 11, 10, 11, 3]);
+}
+
+#[test]
+fn array_initializers() {
+    let mem = eval("
+        arr primes[5] = {2, 3, 5, 7, 11}
+        arr msg = \"Hi\\n\"
+        arr z[2 * 3 - 1] = {
+            9,
+            1 + 1,
+        }
+        arr n = {-1}
+    ");
+    assert_eq!(&mem[..5], &[2, 3, 5, 7, 11]);
+    assert_eq!(&mem[5..9], &[72, 105, 10, 0]);
+    assert_eq!(&mem[9..14], &[9, 2, 0, 0, 0]);
+    assert_eq!(mem[14], -1);
+}
+
+#[test]
+fn array_length_errors() {
+    let src = "var n\narr a[n]\narr b[2] = {1, 2, 3}\narr c[1 - 1]\nconst N = 999999\narr d[N * 999999]\narr e[N * N * N * N]\narr f[-(N * N * N * N) < 0]\nconst M = N * N * N * N - N * N * N * N\n";
+    let errors = compile_errors(src);
+    assert_eq!(messages(&errors), [
+        "Array length must be a constant expression",
+        "Too many values for array \"b\"",
+        "Invalid length 0 for array \"c\"",
+        "Length of array \"d\" out of range",
+        "Length of array \"e\" out of range",
+        "Length of array \"f\" out of range",
+        "Value of constant \"M\" out of range",
+    ]);
+    let lines: Vec<usize> = errors.iter().map(|e| e.span.line).collect();
+    assert_eq!(lines, [2, 3, 4, 6, 7, 8, 9]);
+    assert_eq!(errors[1].notes, ["expected at most 2, found 3"]);
+    assert_eq!(errors[3].notes, ["arrays can have at most 999999 elements"]);
+}
+
+#[test]
+fn named_constants() {
+    let mem = eval("
+        const N = 3
+        const M = N * 2 + 1
+        arr a[M - N] = {N, M}
+        var x = N + M
+        {
+            const N = 100
+            x = x + N
+        }
+        var y = N
+    ");
+    assert_eq!(&mem[..4], &[3, 7, 0, 0]);
+    assert_eq!(mem[4], 110);
+    assert_eq!(mem[5], 3);
+}
+
+#[test]
+fn constants_are_not_assignable() {
+    let src = "const N = 1\nN = 2\nvar p = addr N\nvar v\nconst X = v\n";
+    let errors = compile_errors(src);
+    assert_eq!(messages(&errors), [
+        "Cannot assign to constant \"N\"",
+        "Constant \"N\" has no address",
+        "Value of constant \"X\" must be a constant expression",
+    ]);
+    let lines: Vec<usize> = errors.iter().map(|e| e.span.line).collect();
+    assert_eq!(lines, [2, 3, 5]);
+    assert_eq!(errors[0].labels[0].message, "constant declared here");
+    assert_eq!(errors[0].labels[0].span.line, 1);
+}
+
+#[test]
+fn variables_cannot_reuse_constant_names() {
+    let src = "const K = 4\n{\n    var K\n}\nK = 2\narr K[2]\nfunc f(K) {\n}\nconst K = 5\n";
+    let errors = compile_errors(src);
+    assert_eq!(messages(&errors), [
+        "\"K\" is already declared as a constant",
+        "Cannot assign to constant \"K\"",
+        "\"K\" is already declared as a constant",
+        "\"K\" is already declared as a constant",
+        "\"K\" is already declared as a constant",
+    ]);
+    let lines: Vec<usize> = errors.iter().map(|e| e.span.line).collect();
+    assert_eq!(lines, [3, 5, 6, 7, 9]);
+    assert_eq!(errors[4].labels[0].span.line, 1);
+    assert_eq!(errors[0].labels[0].message, "constant declared here");
+    assert_eq!(errors[0].labels[0].span.line, 1);
+
+    // Eine Konstante darf eine Variable verdecken, danach ist die Variable wieder sichtbar
+    let mem = eval("
+        var K = 1
+        var r
+        {
+            const K = 4
+            r = K
+        }
+        K = K + 1
+    ");
+    assert_eq!(&mem[..2], &[2, 4]);
+}
+
+#[test]
+fn break_and_continue() {
+    let mem = eval("
+        var i
+        var sum
+        i = 0
+        sum = 0
+        while 1 {
+            i = i + 1
+            if i == 3 {
+                continue
+            }
+            if i > 5 {
+                break
+            }
+            sum = sum + i
+        }
+    ");
+    assert_eq!(mem[0], 6);
+    assert_eq!(mem[1], 1 + 2 + 4 + 5);
+}
+
+#[test]
+fn break_outside_of_loop() {
+    // Auch eine Funktion in einer Schleife kann die Schleife nicht verlassen
+    let errors = compile_errors("break\nwhile 1 {\n    func f() {\n        continue\n    }\n}\ncontinue");
+    assert_eq!(messages(&errors), [
+        "\'break\' outside of a loop",
+        "\'continue\' outside of a loop",
+        "\'continue\' outside of a loop",
+    ]);
+    let lines: Vec<usize> = errors.iter().map(|e| e.span.line).collect();
+    assert_eq!(lines, [1, 4, 7]);
+}
+
+#[test]
+fn counted_for_loop() {
+    let mem = eval("
+        var sum
+        var after
+        sum = 0
+        for i = 0 to 5 {
+            if i == 2 {
+                continue
+            }
+            sum = sum + i
+        }
+        // i ist wieder freigegeben, after bekommt die nächste freie Adresse
+        var x
+        x = 7
+    ");
+    assert_eq!(mem[0], 1 + 3 + 4);
+    assert_eq!(mem[2], 7);
+}
+
+#[test]
+fn for_loop_start_sees_outer_variable() {
+    // Der Startwert wird ausgewertet, bevor die Zählvariable deklariert wird
+    let mem = eval_vars("
+        var i = 100
+        var s = 0
+        for i = i to 103 {
+            s = s + i
+        }
+        recursive func f(i) {
+            var t = 0
+            for i = i to 13 {
+                t = t + i
+            }
+            return t
+        }
+        var u = f(10)
+    ", &["i", "s", "u"]);
+    assert_eq!(mem, [100, 100 + 101 + 102, 10 + 11 + 12]);
+}
+
+// ===== Funktionen =====
+
+#[test]
+fn recursive_function() {
+    let mem = eval_vars("
+        var r
+        recursive func fak(n) {
+            if n <= 1 {
+                return 1
+            }
+            return n * fak(n - 1)
+        }
+        r = fak(6)
+    ", &["r"]);
+    assert_eq!(mem, [720]);
+}
+
+#[test]
+fn recursion_keeps_locals_in_frame() {
+    let mem = eval_vars("
+        var r
+        var s
+        recursive func fib(n) {
+            if n < 2 {
+                return n
+            }
+            var a = fib(n - 1)
+            var b = fib(n - 2)
+            return a + b
+        }
+        recursive func f(n) {
+            arr a[2] = {n, n * 2}
+            var p = addr a
+            if n == 0 {
+                return 0
+            }
+            var rest = f(n - 1)
+            deref p = a[0] + deref (p + 1)
+            return a[0] + rest
+        }
+        r = fib(15)
+        s = f(4)
+    ", &["r", "s"]);
+    assert_eq!(mem, [610, 3 * (1 + 2 + 3 + 4)]);
+}
+
+#[test]
+fn function_memory_is_not_reused() {
+    // Die lokale Variable b darf nicht an derselben Adresse wie y liegen
+    let mem = eval_vars("
+        func f() {
+            var b = 2
+            return b + 3
+        }
+        var y = 7
+        var z = f()
+    ", &["y", "z"]);
+    assert_eq!(mem, [7, 5]);
+}
+
+#[test]
+fn stack_frames_flag() {
+    let src = "
+        var r
+        func sum(n) {
+            if n == 0 {
+                return 0
+            }
+            return n + sum(n - 1)
+        }
+        r = sum(100)
+    ";
+    assert_eq!(eval_vars_with(src, true, &["r"]), [5050]);
+
+    let errors = compile_errors(src);
+    assert_eq!(messages(&errors), ["Recursive call of function \"sum\""]);
+    assert_eq!(errors[0].span.line, 7);
+    assert_eq!(errors[0].notes, [
+        "call chain: sum -> sum",
+        "declare the functions with \"recursive func\" or compile with --stack-frames",
+    ]);
+}
+
+#[test]
+fn call_statements_and_implicit_return() {
+    let mem = eval_vars("
+        var x = 0
+        var r = 5
+        var s = 5
+        func erhöhe(n) {
+            x = x + n
+        }
+        call erhöhe(3)
+        erhöhe(4)
+        r = erhöhe(0)
+        recursive func leer() {
+            if x == 0 {
+                return 9
+            }
+        }
+        s = leer()
+    ", &["x", "r", "s"]);
+    assert_eq!(mem, [7, 0, 0]);
+}
+
+#[test]
+fn functions_can_be_called_before_declaration() {
+    let mem = eval_vars("
+        var r = gerade(10)
+        var s = gerade(7)
+        var a = 1 + doppelt(21)
+        func doppelt(x) {
+            return x * 2
+        }
+        recursive func gerade(n) {
+            if n == 0 {
+                return 1
+            }
+            return ungerade(n - 1)
+        }
+        recursive func ungerade(n) {
+            if n == 0 {
+                return 0
+            }
+            return gerade(n - 1)
+        }
+    ", &["r", "s", "a"]);
+    assert_eq!(mem, [1, 0, 43]);
+}
+
+#[test]
+fn duplicate_function_declaration() {
+    let errors = compile_errors("func f() {\n}\nfunc f() {\n}\n");
+    assert_eq!(messages(&errors), ["Function \"f\" is already declared"]);
+    assert_eq!((errors[0].span.line, errors[0].span.column), (3, 6));
+    assert_eq!(errors[0].labels[0].message, "first declared here");
+    assert_eq!((errors[0].labels[0].span.line, errors[0].labels[0].span.column), (1, 6));
+}
+
+#[test]
+fn recursion_cycles_are_reported() {
+    let src = "func a(n) {\n    return b(n)\n}\nfunc b(n) {\n    return c(n) + a(n)\n}\nfunc c(n) {\n    return n\n}\nvar x = a(1)\n";
+    let tokens = lexer(src.to_string(), "test".to_string()).unwrap();
+    let ast = parser::parse(tokens, 20).unwrap();
+    let errors = ast.codegen(false).unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].span.line, 5);
+    assert_eq!(errors[0].labels.len(), 2);
+    assert_eq!(errors[0].notes[0], "call chain: a -> b -> a");
+
+    // Mit Stack-Frames ist der Zyklus erlaubt
+    assert!(ast.codegen(true).is_ok());
+
+    let dot = ast.call_graph(false).to_dot();
+    assert!(dot.contains("\"<program>\" -> \"a\";"));
+    assert!(dot.contains("\"a\" -> \"b\" [color=red];"));
+    assert!(dot.contains("\"b\" -> \"c\";"));
+}
+
+#[test]
+fn nested_and_repeated_calls() {
+    let src = "
+        var r, s, t
+        func f(a, b) {
+            return a * 10 + b
+        }
+        r = f(1, f(2, 3))
+        s = f(f(1, 2), f(3, 4))
+        t = f(4, 5) + f(6, 7)
+    ";
+    assert_eq!(eval_vars(src, &["r", "s", "t"]), [33, 154, 112]);
+    assert_eq!(eval_vars_with(src, true, &["r", "s", "t"]), [33, 154, 112]);
+}